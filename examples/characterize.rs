@@ -0,0 +1,51 @@
+//! Characterises capacitor rise times, for qualifying a capacitor part.
+//!
+//! Logs a summary and histogram of rise times for a range of `low_cycles`.
+
+#![no_std]
+#![no_main]
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, warn};
+use {defmt_rtt as _, panic_probe as _};
+
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Timer};
+
+use caprand::cap::RawNoise;
+use caprand::characterize::{capture, Characterize};
+
+const SAMPLES: usize = 10_000;
+const BINS: usize = 32;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut p = embassy_rp::init(Default::default());
+
+    loop {
+        for low_cycles in [1, 4, 16] {
+            let mut noise = RawNoise::new(p.PIN_10.reborrow(), low_cycles);
+            let median = match noise.check() {
+                Ok(m) => m,
+                Err(e) => {
+                    error!("low_cycles {}: {}", low_cycles, e);
+                    continue;
+                }
+            };
+
+            // Centre the histogram on the median rise time
+            let width = 2;
+            let base = median.saturating_sub(width * BINS as u32 / 2);
+            let mut ch = Characterize::<BINS>::new(base, width);
+            if let Err(e) = capture(&mut noise, &mut ch, SAMPLES) {
+                error!("low_cycles {}: {}", low_cycles, e);
+                continue;
+            }
+
+            info!("low_cycles {}: {}", low_cycles, ch.summary());
+            info!("from {} width {}: {}", base, width, ch.histogram());
+            info!("below, above {}", ch.outside());
+        }
+        Timer::after(Duration::from_millis(1000)).await;
+    }
+}
@@ -0,0 +1,87 @@
+//! Captures samples after each restart, for the SP 800-90B 3.1.4 restart tests.
+//!
+//! The first `SAMPLES` samples after reset are captured, without discarding
+//! any, then sent as a `caprand::stream` frame via the rp2040 uart.
+//! The frame session is the restart number. The device then resets itself
+//! with the watchdog, until `RESTARTS` captures have been sent.
+//!
+//! The restart count is kept in a watchdog scratch register, which is cleared by
+//! a power-on reset. Start the capture by power cycling the device or pressing reset,
+//! then analyse with `caprand-analyze --restart`.
+//!
+//! A watchdog reset doesn't power down the capacitor. Power cycling the board
+//! externally between captures is a stricter test.
+
+#![no_std]
+#![no_main]
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, warn};
+use {defmt_rtt as _, panic_probe as _};
+
+use embassy_executor::Spawner;
+use embassy_rp::gpio::Pin;
+use embassy_rp::uart::{Config, UartTx};
+use embassy_rp::watchdog::{ResetReason, Watchdog};
+use embassy_time::{Duration, Timer};
+
+use caprand::stream::{frame_len, Encoder, Metadata, SourceKind};
+
+/// Samples captured after each restart
+const SAMPLES: usize = 1000;
+/// Number of restarts
+const RESTARTS: u32 = 1000;
+/// Watchdog scratch register holding the restart count
+const SCRATCH: usize = 0;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    // Capture before anything else runs
+    let cap_pin = p.PIN_10;
+    let pin = cap_pin.pin();
+    let low_cycles = 1;
+    let mut buf = [0u8; SAMPLES];
+    caprand::cap::RawNoise::new(cap_pin, low_cycles).fill(&mut buf);
+
+    let mut watchdog = Watchdog::new(p.WATCHDOG);
+    let restart = match watchdog.reset_reason() {
+        Some(ResetReason::Forced) => watchdog.get_scratch(SCRATCH),
+        // power on or reset pin
+        _ => 0,
+    };
+
+    let mut conf = Config::default();
+    conf.baudrate = 2500000;
+    let mut tx = UartTx::new_blocking(p.UART0, p.PIN_0, conf);
+
+    let mut enc = Encoder::new(Metadata {
+        source: SourceKind::CapRise,
+        session: restart,
+        clk_sys: embassy_rp::clocks::clk_sys_freq(),
+        low_cycles,
+        pin,
+        firmware: [
+            env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+            env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+            env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+        ],
+        tag: *b"10nF Y5V",
+    });
+    let mut frame = [0u8; frame_len(SAMPLES)];
+    let l = enc.encode(&buf, &mut frame).unwrap();
+    tx.blocking_write(&frame[..l]).unwrap();
+    tx.blocking_flush().unwrap();
+
+    if restart + 1 < RESTARTS {
+        watchdog.set_scratch(SCRATCH, restart + 1);
+        watchdog.trigger_reset();
+    } else {
+        info!("Restart capture complete, {} restarts", RESTARTS);
+    }
+
+    loop {
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,134 @@
+//! Measures the time taken to seed from the capacitor, in SysTick cycles.
+//!
+//! Both sides run the same pipeline of health tests, entropy crediting and
+//! hashing until a seed is ready. They differ only in sampling and hashing
+//! each sample individually (the previous implementation), or whole blocks
+//! collected with `RawNoise::fill()` as `CapRng::new()` does.
+//! Warm-up and the capacitor check are excluded.
+
+#![no_std]
+#![no_main]
+
+use core::cell::Cell;
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, warn};
+use {defmt_rtt as _, panic_probe as _};
+
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use cortex_m_rt::exception;
+use critical_section::Mutex;
+use embassy_executor::Spawner;
+use embassy_rp::gpio::Pin;
+use embassy_rp::Peri;
+use embassy_time::{Duration, Timer};
+
+use caprand::cap::{RawNoise, Sample};
+use caprand::health::TotalHealth;
+use caprand::pool::{EntropyPool, Estimate};
+use caprand::CapRng;
+
+/// SysTick reload, the maximum 24 bit count.
+const RELOAD: u32 = 0x00ff_ffff;
+
+/// Samples collected at a time by the batched pipeline, as `CapRng`.
+const BLOCK: usize = 256;
+
+/// SysTick wraps since start.
+static WRAPS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+#[exception]
+fn SysTick() {
+    critical_section::with(|cs| {
+        let w = WRAPS.borrow(cs);
+        w.set(w.get() + 1);
+    });
+}
+
+/// Returns core clock cycles since SysTick started.
+///
+/// Must be called with interrupts enabled, so that wraps are counted.
+fn cycles() -> u64 {
+    loop {
+        let before = critical_section::with(|cs| WRAPS.borrow(cs).get());
+        let current = SYST::get_current();
+        let after = critical_section::with(|cs| WRAPS.borrow(cs).get());
+        if before == after {
+            return before as u64 * (RELOAD as u64 + 1) + (RELOAD - current) as u64;
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut p = embassy_rp::init(Default::default());
+
+    let mut syst = cortex_m::Peripherals::take().unwrap().SYST;
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(RELOAD);
+    syst.clear_current();
+    syst.enable_interrupt();
+    syst.enable_counter();
+
+    loop {
+        let t = cycles();
+        per_sample(p.PIN_10.reborrow());
+        let before = cycles() - t;
+
+        let t = cycles();
+        batched(p.PIN_10.reborrow());
+        let after = cycles() - t;
+
+        info!("Seed cycles: per-sample {}, batched {}", before, after);
+        Timer::after(Duration::from_millis(1000)).await;
+    }
+}
+
+/// Samples, health tests and hashes each sample individually.
+///
+/// Each sample takes a separate critical section and `Sha256::update()`.
+fn per_sample(pin: Peri<impl Pin>) -> [u8; 32] {
+    let mut noise = RawNoise::new(pin, 1);
+    let mut health = TotalHealth::new();
+    let mut pool = EntropyPool::new(Estimate::default(), CapRng::SEED_BITS);
+
+    while !pool.ready() {
+        let Some(Sample::Value(v, valid)) = noise.next() else {
+            panic!("Timeout");
+        };
+        if valid {
+            pool.credit(v).unwrap();
+            if health.test(v).is_err() {
+                pool.discredit();
+            }
+        }
+        pool.mix(&[v]);
+    }
+    pool.take_seed().unwrap()
+}
+
+/// Samples, health tests and hashes blocks of samples.
+fn batched(pin: Peri<impl Pin>) -> [u8; 32] {
+    let mut noise = RawNoise::new(pin, 1);
+    let mut health = TotalHealth::new();
+    let mut pool = EntropyPool::new(Estimate::default(), CapRng::SEED_BITS);
+
+    let mut block = [0u8; BLOCK];
+    while !pool.ready() {
+        noise.fill(&mut block);
+        for v in block.iter().copied() {
+            if caprand::cap::timeout(v) {
+                panic!("Timeout");
+            }
+            if caprand::cap::valid(v) {
+                pool.credit(v).unwrap();
+                if health.test(v).is_err() {
+                    pool.discredit();
+                }
+            }
+        }
+        pool.mix(&block);
+    }
+    pool.take_seed().unwrap()
+}
@@ -1,6 +1,8 @@
 //! Prints raw samples from the capacitor random number generator.
 //! These would not usually be used directly, instead use `CapRng`.
 //! Raw samples are useful to analyse the entropy.
+//!
+//! Samples are sent as `caprand::stream` binary frames via the rp2040 uart.
 
 #![no_std]
 #![no_main]
@@ -12,10 +14,16 @@ use {defmt_rtt as _, panic_probe as _};
 use embassy_executor::Spawner;
 use embassy_rp::bind_interrupts;
 use embassy_rp::gpio;
+use embassy_rp::gpio::Pin;
 use embassy_rp::peripherals::UART0;
 use embassy_rp::uart::{BufferedInterruptHandler, BufferedUart, Config};
 use embedded_io_async::Write as _;
 
+use caprand::stream::{frame_len, Encoder, Metadata, SourceKind};
+
+/// Samples per frame
+const SAMPLES: usize = 4096;
+
 bind_interrupts!(struct Irqs {
     UART0_IRQ => BufferedInterruptHandler<UART0>;
 });
@@ -46,23 +54,36 @@ async fn main(_spawner: Spawner) {
     let (mut tx, _rx) = uart.split();
 
     let cap_pin = p.PIN_10;
-
-    let mut noise = caprand::cap::RawNoise::new(cap_pin, 1);
+    let pin = cap_pin.pin();
+    let low_cycles = 1;
+
+    let mut noise = caprand::cap::RawNoise::new(cap_pin, low_cycles);
+
+    let mut enc = Encoder::new(Metadata {
+        source: SourceKind::CapRise,
+        session: 0,
+        clk_sys: embassy_rp::clocks::clk_sys_freq(),
+        low_cycles,
+        pin,
+        firmware: [
+            env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+            env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+            env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+        ],
+        tag: *b"10nF Y5V",
+    });
 
     loop {
         led.toggle();
 
-        let mut buf = [0u8; 20000];
-        for b in buf.iter_mut() {
-            (*b, _) = noise.next().unwrap();
-        }
-
-        for b in buf {
-            let b = caprand::cap::lsb(b);
-            let line = [nibble_hex(b >> 4), nibble_hex(b & 0xf), b'\r', b'\n'];
-            // let line = [nibble_hex(b >> 4), nibble_hex(b & 0xf), b'\r', b'\n'];
-            tx.write_all(&line).await.unwrap();
-        }
+        // Samples are collected before writing, so that UART activity
+        // doesn't coincide with sampling.
+        let mut buf = [0u8; SAMPLES];
+        noise.fill(&mut buf);
+
+        let mut frame = [0u8; frame_len(SAMPLES)];
+        let l = enc.encode(&buf, &mut frame).unwrap();
+        tx.write_all(&frame[..l]).await.unwrap();
     }
 
     // // Do stuff with the class!
@@ -80,15 +101,6 @@ async fn main(_spawner: Spawner) {
     // join(usb_fut, echo_fut).await;
 }
 
-fn nibble_hex(c: u8) -> u8 {
-    debug_assert!(c <= 0xf);
-    if c < 10 {
-        b'0' + c
-    } else {
-        b'a' - 0xa + c
-    }
-}
-
 // async fn run<'d, D: embassy_usb_driver::Driver<'d>>(pin: &mut impl Pin, class: &mut CdcAcmClass<'d, D>) -> Result<(), ()> {
 
 //     let low_cycles = 1;
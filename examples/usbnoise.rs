@@ -1,6 +1,12 @@
-//! Prints raw samples from the capacitor random number generator.
+//! Outputs raw samples from the capacitor random number generator
+//! as a USB vendor class device.
 //! These would not usually be used directly, instead use `CapRng`.
 //! Raw samples are useful to analyse the entropy.
+//!
+//! The host selects raw samples, conditioned or full entropy output,
+//! or health counters, see `caprand::usbproto`. Requires the `usb` feature.
+//!
+//! With `host/caprand-rngd` it can be used as a hardware RNG for Linux.
 
 #![no_std]
 #![no_main]
@@ -9,17 +15,16 @@
 use defmt::{debug, error, info, warn};
 use {defmt_rtt as _, panic_probe as _};
 
-use core::fmt::Write;
-
 use embassy_executor::Spawner;
 use embassy_futures::join::join;
-use embassy_rp::gpio::Pin;
+use embassy_rp::bind_interrupts;
 use embassy_rp::peripherals::USB;
 use embassy_rp::usb::InterruptHandler;
-use embassy_rp::{bind_interrupts, Peri};
-use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
 use embassy_usb::{Builder, Config};
 
+use caprand::stream::{Metadata, SourceKind};
+use caprand::usb::{State, UsbEntropy};
+
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
 });
@@ -27,7 +32,7 @@ bind_interrupts!(struct Irqs {
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
     info!("top");
-    let mut p = embassy_rp::init(Default::default());
+    let p = embassy_rp::init(Default::default());
 
     // Create the driver, from the HAL.
     let driver = embassy_rp::usb::Driver::new(p.USB, Irqs);
@@ -40,13 +45,6 @@ async fn main(_spawner: Spawner) {
     config.max_power = 100;
     config.max_packet_size_0 = 64;
 
-    // Required for windows compatiblity.
-    // https://developer.nordicsemi.com/nRF_Connect_SDK/doc/1.9.1/kconfig/CONFIG_CDC_ACM_IAD.html#help
-    config.device_class = 0xEF;
-    config.device_sub_class = 0x02;
-    config.device_protocol = 0x01;
-    config.composite_with_iads = true;
-
     // Create embassy-usb DeviceBuilder using the driver and config.
     // It needs some buffers for building the descriptors.
     let mut config_descriptor = [0; 256];
@@ -65,8 +63,21 @@ async fn main(_spawner: Spawner) {
         &mut control_buf,
     );
 
-    // Create classes on the builder.
-    let mut class = CdcAcmClass::new(&mut builder, &mut state, 64);
+    let meta = Metadata {
+        source: SourceKind::CapRise,
+        session: 0,
+        // filled by UsbEntropy
+        clk_sys: 0,
+        low_cycles: 0,
+        pin: 0,
+        firmware: [
+            env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+            env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+            env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+        ],
+        tag: *b"10nF Y5V",
+    };
+    let mut dev = UsbEntropy::new(&mut builder, &mut state, meta);
 
     // Build the builder.
     let mut usb = builder.build();
@@ -74,40 +85,7 @@ async fn main(_spawner: Spawner) {
     // Run the USB device.
     let usb_fut = usb.run();
 
-    // Do stuff with the class!
-    let echo_fut = async {
-        loop {
-            class.wait_connection().await;
-            info!("Connected");
-            let pin = p.PIN_10.reborrow();
-            let _ = run(pin, &mut class).await;
-            info!("Disconnected");
-        }
-    };
-
-    join(usb_fut, echo_fut).await;
-}
+    let noise_fut = dev.run(p.PIN_10);
 
-async fn run<'d, D: embassy_usb_driver::Driver<'d>>(
-    pin: Peri<'_, impl Pin>,
-    class: &mut CdcAcmClass<'d, D>,
-) -> Result<(), ()> {
-    let low_cycles = 1;
-    let mut noise = caprand::cap::RawNoise::new(pin, low_cycles);
-
-    // discard one sample
-    noise.next();
-
-    loop {
-        // usb packet has 64 limit
-        let mut b = heapless::String::<64>::new();
-        while b.len() <= b.capacity() - 2 {
-            let (c, _valid) = noise.next().unwrap();
-            write!(b, "{:02x}\n", c).unwrap();
-            // if valid {
-            //     write!(b, "{}\n", caprand::cap::lsb(c)).unwrap();
-            // }
-        }
-        class.write_packet(b.as_bytes()).await.unwrap()
-    }
+    join(usb_fut, noise_fut).await;
 }
@@ -0,0 +1,122 @@
+//! Greyscale image rendering, written as PNG or PGM.
+
+use std::io::{self, Write};
+
+use caprand::stream::crc32;
+
+/// An 8 bit greyscale image.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, background: u8) -> Self {
+        Self { width, height, pixels: vec![background; width * height] }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, v: u8) {
+        self.pixels[y * self.width + x] = v;
+    }
+
+    /// Writes a binary PGM.
+    pub fn write_pgm(&self, w: &mut impl Write) -> io::Result<()> {
+        write!(w, "P5\n{} {}\n255\n", self.width, self.height)?;
+        w.write_all(&self.pixels)
+    }
+
+    /// Writes a PNG, uncompressed.
+    pub fn write_png(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut ihdr = Vec::new();
+        ihdr.extend((self.width as u32).to_be_bytes());
+        ihdr.extend((self.height as u32).to_be_bytes());
+        // 8 bit greyscale, deflate, adaptive filtering, no interlace
+        ihdr.extend([8, 0, 0, 0, 0]);
+        chunk(w, b"IHDR", &ihdr)?;
+
+        // each row is prefixed by filter type 0, none
+        let mut raw = Vec::with_capacity((self.width + 1) * self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            raw.push(0);
+            raw.extend(row);
+        }
+        chunk(w, b"IDAT", &zlib_stored(&raw))?;
+        chunk(w, b"IEND", &[])
+    }
+
+    /// Writes PGM if `path` ends with `.pgm`, otherwise PNG.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut f = io::BufWriter::new(std::fs::File::create(path)?);
+        if path.ends_with(".pgm") {
+            self.write_pgm(&mut f)?;
+        } else {
+            self.write_png(&mut f)?;
+        }
+        f.flush()
+    }
+}
+
+fn chunk(w: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut c = kind.to_vec();
+    c.extend(data);
+    w.write_all(&c)?;
+    w.write_all(&crc32(&c).to_be_bytes())
+}
+
+/// Wraps data in a zlib stream of stored (uncompressed) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(b) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        out.extend((b.len() as u16).to_le_bytes());
+        out.extend((!(b.len() as u16)).to_le_bytes());
+        out.extend(b);
+    }
+
+    // Adler-32
+    let (mut a, mut b) = (1u32, 0u32);
+    for d in data {
+        a = (a + *d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend((b << 16 | a).to_be_bytes());
+    out
+}
+
+/// Renders one pixel per sample, rows of `width`.
+///
+/// Trailing samples that don't fill a row are omitted.
+pub fn bitmap(pixels: impl Iterator<Item = u8>, n: usize, width: usize) -> Image {
+    let width = width.max(1);
+    let mut im = Image::new(width, n / width, 0);
+    for (i, v) in pixels.take(im.pixels.len()).enumerate() {
+        im.pixels[i] = v;
+    }
+    im
+}
+
+/// Renders a bar chart of `counts`, black on white.
+pub fn histogram(counts: &[u64]) -> Image {
+    const BAR: usize = 2;
+    const HEIGHT: usize = 200;
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+    let mut im = Image::new(counts.len() * BAR, HEIGHT, 0xff);
+    for (i, c) in counts.iter().enumerate() {
+        let h = (*c * HEIGHT as u64).div_ceil(max) as usize;
+        for y in HEIGHT - h..HEIGHT {
+            for x in i * BAR..(i + 1) * BAR {
+                im.set(x, y, 0);
+            }
+        }
+    }
+    im
+}
@@ -0,0 +1,138 @@
+//! Reading captures.
+
+use std::str::FromStr;
+
+use caprand::stream::{self, Continuity, Decoder, Metadata};
+
+/// Capture file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Hex digits, whitespace is ignored. As `xxd -p` output.
+    Hex,
+    /// One sample per byte.
+    Bin,
+    /// [`stream`] frames, from the sernoise or usbnoise examples.
+    Frames,
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "hex" => Ok(Self::Hex),
+            "bin" => Ok(Self::Bin),
+            "frames" => Ok(Self::Frames),
+            _ => Err(format!("unknown format {s}")),
+        }
+    }
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Bin => "bin",
+            Self::Frames => "frames",
+        }
+    }
+
+    /// Guesses the format of a capture.
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(&stream::MAGIC) {
+            Self::Frames
+        } else if !data.is_empty()
+            && data.iter().all(|b| b.is_ascii_hexdigit() || b.is_ascii_whitespace())
+        {
+            Self::Hex
+        } else {
+            Self::Bin
+        }
+    }
+}
+
+/// Samples read from a capture.
+#[derive(Default)]
+pub struct Capture {
+    pub samples: Vec<u8>,
+    /// Metadata of the first frame.
+    pub meta: Option<Metadata>,
+    pub frames: usize,
+    /// Frames failing to decode, skipped.
+    pub bad_frames: usize,
+    /// Samples lost between frames.
+    pub lost_samples: u64,
+    /// Start index in `samples` of each session.
+    pub session_starts: Vec<usize>,
+}
+
+impl Capture {
+    pub fn read(data: &[u8], format: Format) -> Result<Self, String> {
+        match format {
+            Format::Bin => Ok(Self { samples: data.to_vec(), ..Default::default() }),
+            Format::Hex => Self::hex(data),
+            Format::Frames => Ok(Self::frames(data)),
+        }
+    }
+
+    fn hex(data: &[u8]) -> Result<Self, String> {
+        let digits: Vec<u8> =
+            data.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+        if !digits.len().is_multiple_of(2) {
+            return Err("Odd number of hex digits".into());
+        }
+        let samples = digits
+            .chunks(2)
+            .map(|d| {
+                std::str::from_utf8(d)
+                    .ok()
+                    .and_then(|d| u8::from_str_radix(d, 16).ok())
+                    .ok_or_else(|| {
+                        format!("Bad hex {:?}", String::from_utf8_lossy(d))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { samples, ..Default::default() })
+    }
+
+    /// Returns the samples of each session.
+    pub fn session_samples(&self) -> impl Iterator<Item = &[u8]> {
+        let ends = self.session_starts.iter().skip(1).copied();
+        let ends = ends.chain([self.samples.len()]);
+        self.session_starts.iter().zip(ends).map(|(s, e)| &self.samples[*s..e])
+    }
+
+    fn frames(mut data: &[u8]) -> Self {
+        let mut c = Self::default();
+        let mut dec = Decoder::new();
+        while !data.is_empty() {
+            match stream::decode(data) {
+                Ok((f, l)) => {
+                    match dec.check(&f) {
+                        Continuity::Contiguous => (),
+                        Continuity::NewSession => {
+                            c.session_starts.push(c.samples.len());
+                        }
+                        Continuity::Gap { samples, .. } => {
+                            c.lost_samples += samples as u64
+                        }
+                    }
+                    c.meta.get_or_insert(f.meta);
+                    c.samples.extend(f.samples);
+                    c.frames += 1;
+                    data = &data[l..];
+                }
+                Err(stream::Error::Short) if !data.starts_with(&stream::MAGIC) => {
+                    break;
+                }
+                Err(_) => {
+                    c.bad_frames += 1;
+                    // resynchronise past the bad frame's magic
+                    let skip =
+                        stream::find_magic(&data[1..]).map_or(data.len(), |p| p + 1);
+                    data = &data[skip..];
+                }
+            }
+        }
+        c
+    }
+}
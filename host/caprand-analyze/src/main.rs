@@ -0,0 +1,296 @@
+//! Analyses raw noise captures.
+//!
+//! Reads a capture of raw samples and writes a JSON report to stdout,
+//! optionally rendering a bitmap of samples and a histogram as images.
+//!
+//! Captures may be hex (as the previous usbnoise output, or `xxd -p`), binary
+//! with one sample per byte, or [`caprand::stream`] frames from the sernoise
+//! example or `caprand-usb`.
+//!
+//! With `--restart` the restart tests of SP 800-90B 3.1.4 are run instead,
+//! on frames captured by the restart example.
+//!
+//! Capacitor samples are analysed as the position of the first bit set,
+//! only valid samples (see `caprand::cap::valid()`) are used for tests
+//! and entropy estimates. ADC samples are analysed as raw values.
+
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+use caprand::stream::SourceKind;
+
+mod image;
+mod input;
+mod json;
+mod restart;
+mod stats;
+
+use input::{Capture, Format};
+use json::Value;
+use stats::Estimates;
+
+const USAGE: &str = "\
+Usage: caprand-analyze [options] CAPTURE
+
+Reads CAPTURE (or - for stdin) and writes a JSON report to stdout.
+
+Options:
+  --format FORMAT     hex, bin or frames, default is detected
+  --adc               Samples are from an ADC, not a capacitor.
+                      Implied by frames with ADC metadata
+  --bitmap FILE       Write an image with a pixel per sample
+  --width N           Bitmap width, default is square
+  --histogram FILE    Write a histogram of sample values
+  --lags N            Autocorrelation lags, default 16
+  --restart           Run restart tests, each session of frames is a restart
+  --h-initial BITS    Initial entropy estimate for restart tests,
+                      default is the restart row dataset estimate
+  -h, --help          Show this help
+
+Images are written as PNG, or PGM if FILE ends with .pgm
+";
+
+struct Args {
+    format: Option<Format>,
+    adc: bool,
+    bitmap: Option<String>,
+    width: Option<usize>,
+    histogram: Option<String>,
+    lags: usize,
+    restart: bool,
+    h_initial: Option<f64>,
+    capture: String,
+}
+
+fn parse_args() -> Result<Args, pico_args::Error> {
+    let mut p = pico_args::Arguments::from_env();
+    if p.contains(["-h", "--help"]) {
+        print!("{USAGE}");
+        std::process::exit(0);
+    }
+    let args = Args {
+        format: p.opt_value_from_str("--format")?,
+        adc: p.contains("--adc"),
+        bitmap: p.opt_value_from_str("--bitmap")?,
+        width: p.opt_value_from_str("--width")?,
+        histogram: p.opt_value_from_str("--histogram")?,
+        lags: p.opt_value_from_str("--lags")?.unwrap_or(16),
+        restart: p.contains("--restart"),
+        h_initial: p.opt_value_from_str("--h-initial")?,
+        capture: p.free_from_str()?,
+    };
+    let rest = p.finish();
+    if !rest.is_empty() {
+        return Err(pico_args::Error::ArgumentParsingFailed {
+            cause: format!("unexpected arguments {rest:?}"),
+        });
+    }
+    Ok(args)
+}
+
+fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut d = Vec::new();
+        io::stdin().read_to_end(&mut d)?;
+        Ok(d)
+    } else {
+        std::fs::read(path)
+    }
+}
+
+fn metadata(c: &Capture) -> Value {
+    let Some(m) = &c.meta else {
+        return Value::Null;
+    };
+    let tag = m.tag.iter().take_while(|b| **b != 0).map(|b| *b as char);
+    Value::Obj(vec![
+        ("source", format!("{:?}", m.source).as_str().into()),
+        ("session", m.session.into()),
+        ("clk_sys", m.clk_sys.into()),
+        ("low_cycles", m.low_cycles.into()),
+        ("pin", (m.pin as u32).into()),
+        ("firmware", m.firmware.map(|v| v as u32).to_vec().into()),
+        ("tag", tag.collect::<String>().as_str().into()),
+    ])
+}
+
+impl From<Estimates> for Value {
+    fn from(e: Estimates) -> Self {
+        Value::Obj(vec![
+            ("mcv", e.mcv.into()),
+            ("t_tuple", e.t_tuple.into()),
+            ("lag_prediction", e.lag_prediction.into()),
+            ("min", e.min().into()),
+        ])
+    }
+}
+
+fn report(
+    args: &Args,
+    format: Format,
+    bytes: usize,
+    c: &Capture,
+    adc: bool,
+) -> Value {
+    let s = &c.samples;
+    let mut byte_counts = [0u64; 256];
+    for v in s {
+        byte_counts[*v as usize] += 1;
+    }
+    let bytes_hist = byte_counts
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| **n > 0)
+        .map(|(v, n)| Value::Arr(vec![v.into(), (*n).into()]))
+        .collect();
+
+    let mut fields = vec![
+        (
+            "input",
+            Value::Obj(vec![
+                ("format", format.name().into()),
+                ("bytes", bytes.into()),
+                ("frames", c.frames.into()),
+                ("bad_frames", c.bad_frames.into()),
+                ("lost_samples", c.lost_samples.into()),
+                ("sessions", c.session_starts.len().into()),
+            ]),
+        ),
+        ("metadata", metadata(c)),
+        ("source", if adc { "adc" } else { "capacitor" }.into()),
+        // [value, count] pairs
+        ("byte_frequency", Value::Arr(bytes_hist)),
+    ];
+
+    let symbols: Vec<u8> = if adc {
+        symbols(s, adc)
+    } else {
+        let mut lsb = [0u64; 6];
+        let mut timeouts = 0u64;
+        for v in s {
+            if stats::timeout(*v) {
+                timeouts += 1;
+            } else {
+                lsb[stats::lsb(*v) as usize] += 1;
+            }
+        }
+        let valid = s.iter().filter(|v| stats::valid(**v)).count();
+        fields.push((
+            "samples",
+            Value::Obj(vec![
+                ("total", s.len().into()),
+                ("valid", valid.into()),
+                ("invalid", (s.len() - valid - timeouts as usize).into()),
+                ("timeouts", timeouts.into()),
+            ]),
+        ));
+        fields.push((
+            "lsb",
+            Value::Obj(vec![
+                ("counts", lsb.to_vec().into()),
+                ("expected_13ths", stats::LSB_EXPECTED.to_vec().into()),
+                ("chi2", stats::lsb_chi2(&lsb).into()),
+                ("df", 5u32.into()),
+            ]),
+        ));
+        symbols(s, adc)
+    };
+
+    let runs = stats::runs(&symbols);
+    fields.extend([
+        ("analysed_samples", symbols.len().into()),
+        ("autocorrelation", stats::autocorrelation(&symbols, args.lags).into()),
+        (
+            "runs",
+            Value::Obj(vec![
+                ("median", (runs.median as u32).into()),
+                ("runs", runs.runs.into()),
+                ("expected", runs.expected.into()),
+                ("z", runs.z.into()),
+                ("longest_repeat", runs.longest_repeat.into()),
+            ]),
+        ),
+        // bits per analysed sample
+        (
+            "min_entropy",
+            (symbols.len() >= 2).then(|| Estimates::new(&symbols)).into(),
+        ),
+    ]);
+    Value::Obj(fields)
+}
+
+/// Returns the symbols analysed for samples.
+///
+/// Capacitor samples are mapped to the [`stats::lsb()`] of valid samples,
+/// ADC samples are used as is.
+fn symbols(s: &[u8], adc: bool) -> Vec<u8> {
+    if adc {
+        s.to_vec()
+    } else {
+        s.iter().filter(|v| stats::valid(**v)).map(|v| stats::lsb(*v)).collect()
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let data = read_file(&args.capture)?;
+    let format = args.format.unwrap_or_else(|| Format::detect(&data));
+    let c = Capture::read(&data, format)?;
+    if c.samples.is_empty() {
+        return Err("No samples".into());
+    }
+    let adc =
+        args.adc || c.meta.as_ref().is_some_and(|m| m.source == SourceKind::Adc);
+
+    if args.restart {
+        let sessions: Vec<Vec<u8>> =
+            c.session_samples().map(|s| symbols(s, adc)).collect();
+        let m = restart::Matrix::new(sessions.iter().map(|s| s.as_slice()));
+        println!("{}", m.report(args.h_initial)?);
+        return Ok(());
+    }
+
+    println!("{}", report(args, format, data.len(), &c, adc));
+
+    if let Some(path) = &args.bitmap {
+        let n = c.samples.len();
+        let width = args.width.unwrap_or(n.isqrt());
+        let pixels = c.samples.iter().map(|v| {
+            if adc {
+                *v
+            } else if stats::timeout(*v) {
+                0
+            } else {
+                // grey levels from 50 to 200, as docs/25600.png
+                50 + stats::lsb(*v) * 30
+            }
+        });
+        image::bitmap(pixels, n, width).save(path)?;
+    }
+
+    if let Some(path) = &args.histogram {
+        let mut counts = [0u64; 256];
+        for v in &c.samples {
+            counts[*v as usize] += 1;
+        }
+        image::histogram(&counts).save(path)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Restart tests.
+//!
+//! Ref NIST SP 800-90B 3.1.4
+//!
+//! Each row of the restart matrix holds the first samples after a restart of
+//! the noise source, captured by the restart example as one session per row.
+//! Rows hold the same symbols as other analysis, the lowest set bit of valid
+//! capacitor samples.
+//! The standard requires 1000 restarts of 1000 samples.
+
+use crate::json::Value;
+use crate::stats::{self, Estimates};
+
+/// A matrix of samples, a row per restart.
+pub struct Matrix {
+    rows: Vec<Vec<u8>>,
+    cols: usize,
+}
+
+impl Matrix {
+    /// Creates a matrix from sessions, truncated to the shortest.
+    pub fn new<'a>(sessions: impl Iterator<Item = &'a [u8]>) -> Self {
+        let rows: Vec<Vec<u8>> = sessions.map(|s| s.to_vec()).collect();
+        let cols = rows.iter().map(|r| r.len()).min().unwrap_or(0);
+        let rows = rows.into_iter().map(|mut r| {
+            r.truncate(cols);
+            r
+        });
+        Self { rows: rows.collect(), cols }
+    }
+
+    /// Rows concatenated.
+    fn row_data(&self) -> Vec<u8> {
+        self.rows.concat()
+    }
+
+    /// Columns concatenated.
+    fn column_data(&self) -> Vec<u8> {
+        (0..self.cols).flat_map(|c| self.rows.iter().map(move |r| r[c])).collect()
+    }
+
+    /// Returns the maximum count of any value within a row or column.
+    fn max_counts(&self) -> (u64, u64) {
+        let max = |s: &mut dyn Iterator<Item = u8>| {
+            let mut counts = [0u64; 256];
+            s.for_each(|v| counts[v as usize] += 1);
+            counts.into_iter().max().unwrap_or(0)
+        };
+        let row = self.rows.iter().map(|r| max(&mut r.iter().copied())).max();
+        let col =
+            (0..self.cols).map(|c| max(&mut self.rows.iter().map(|r| r[c]))).max();
+        (row.unwrap_or(0), col.unwrap_or(0))
+    }
+
+    /// Runs the restart tests, returning a report.
+    ///
+    /// `h_initial` is the initial entropy estimate `H_I` in bits per sample.
+    /// If not given the row dataset estimate is used.
+    pub fn report(&self, h_initial: Option<f64>) -> Result<Value, String> {
+        let (r, c) = (self.rows.len(), self.cols);
+        if r < 2 || c < 2 {
+            return Err(format!("Restart matrix {r}x{c} is too small"));
+        }
+
+        let row_est = Estimates::new(&self.row_data());
+        let col_est = Estimates::new(&self.column_data());
+        let (h_r, h_c) = (row_est.min(), col_est.min());
+        let h_i = h_initial.unwrap_or(h_r);
+
+        // Sanity check, 3.1.4.3
+        let alpha = 0.01 / (r + c) as f64;
+        let p = 2f64.powf(-h_i);
+        let row_cutoff = stats::binomial_quantile(c as u64, p, alpha);
+        let col_cutoff = stats::binomial_quantile(r as u64, p, alpha);
+        let (row_max, col_max) = self.max_counts();
+        let sanity = row_max <= row_cutoff && col_max <= col_cutoff;
+
+        // Validation, 3.1.4.2
+        let h_restart = h_i.min(h_r).min(h_c);
+        let valid = sanity && h_r.min(h_c) >= h_i / 2.0;
+
+        Ok(Value::Obj(vec![
+            ("rows", r.into()),
+            ("columns", c.into()),
+            ("h_initial", h_i.into()),
+            (
+                "sanity",
+                Value::Obj(vec![
+                    ("max_row_count", row_max.into()),
+                    ("row_cutoff", row_cutoff.into()),
+                    ("max_column_count", col_max.into()),
+                    ("column_cutoff", col_cutoff.into()),
+                    ("pass", sanity.into()),
+                ]),
+            ),
+            ("row_min_entropy", row_est.into()),
+            ("column_min_entropy", col_est.into()),
+            ("h_restart", h_restart.into()),
+            ("pass", valid.into()),
+        ]))
+    }
+}
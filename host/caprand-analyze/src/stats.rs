@@ -0,0 +1,380 @@
+//! Statistical tests and min-entropy estimates.
+//!
+//! Estimators are from NIST SP 800-90B section 6.3, those applicable to
+//! non-binary samples.
+
+use std::collections::HashMap;
+
+/// Capacitor sample helpers, shared with the firmware.
+pub use caprand::sample::{lsb, timeout, valid};
+
+/// Expected distribution of `lsb()` of capacitor samples, in thirteenths.
+///
+/// As `caprand::health::LsbMonitor`.
+pub const LSB_EXPECTED: [u64; 6] = [8, 1, 1, 1, 1, 1];
+
+/// Returns the chi-squared statistic of `lsb()` counts.
+pub fn lsb_chi2(counts: &[u64; 6]) -> f64 {
+    let n: u64 = counts.iter().sum();
+    counts
+        .iter()
+        .zip(LSB_EXPECTED)
+        .map(|(c, e)| {
+            let e = (n * e) as f64 / 13.0;
+            (*c as f64 - e).powi(2) / e
+        })
+        .sum()
+}
+
+/// Autocorrelation at lags `1..=lags`.
+pub fn autocorrelation(s: &[u8], lags: usize) -> Vec<f64> {
+    let n = s.len() as f64;
+    let mean = s.iter().map(|v| *v as f64).sum::<f64>() / n;
+    let var: f64 = s.iter().map(|v| (*v as f64 - mean).powi(2)).sum();
+    (1..=lags)
+        .map(|k| {
+            let c: f64 = s
+                .iter()
+                .zip(s.iter().skip(k))
+                .map(|(a, b)| (*a as f64 - mean) * (*b as f64 - mean))
+                .sum();
+            c / var
+        })
+        .collect()
+}
+
+/// Wald-Wolfowitz runs test, above and below the median.
+pub struct Runs {
+    pub median: u8,
+    pub runs: u64,
+    pub expected: f64,
+    /// Standard score, values beyond ±3 are suspicious.
+    pub z: f64,
+    /// Longest run of identical samples.
+    pub longest_repeat: u64,
+}
+
+pub fn runs(s: &[u8]) -> Runs {
+    let mut sorted = s.to_vec();
+    sorted.sort_unstable();
+    let median = sorted.get(sorted.len() / 2).copied().unwrap_or(0);
+
+    // samples equal to the median are omitted
+    let above: Vec<bool> =
+        s.iter().filter(|v| **v != median).map(|v| *v > median).collect();
+    let runs = above.windows(2).filter(|w| w[0] != w[1]).count() as u64
+        + !above.is_empty() as u64;
+    let n1 = above.iter().filter(|a| **a).count() as f64;
+    let n2 = above.len() as f64 - n1;
+    let n = n1 + n2;
+    let expected = 2.0 * n1 * n2 / n + 1.0;
+    let var = (expected - 1.0) * (expected - 2.0) / (n - 1.0);
+    let z = (runs as f64 - expected) / var.sqrt();
+
+    let mut longest_repeat = 0;
+    let mut cur = 0;
+    for (i, v) in s.iter().enumerate() {
+        cur = if i > 0 && s[i - 1] == *v { cur + 1 } else { 1 };
+        longest_repeat = longest_repeat.max(cur);
+    }
+
+    Runs { median, runs, expected, z, longest_repeat }
+}
+
+/// Upper bound of the 99% confidence interval of a proportion.
+fn upper_bound(p: f64, n: usize) -> f64 {
+    (p + 2.576 * (p * (1.0 - p) / (n as f64 - 1.0)).sqrt()).min(1.0)
+}
+
+/// Most Common Value estimate, bits per sample.
+///
+/// Ref 6.3.1
+pub fn mcv(s: &[u8]) -> f64 {
+    let mut counts = [0u64; 256];
+    for v in s {
+        counts[*v as usize] += 1;
+    }
+    let max = counts.iter().max().copied().unwrap_or(0);
+    let p = max as f64 / s.len() as f64;
+    -upper_bound(p, s.len()).log2()
+}
+
+/// t-Tuple estimate, bits per sample.
+///
+/// Ref 6.3.5
+pub fn t_tuple(s: &[u8]) -> f64 {
+    const CUTOFF: u64 = 35;
+    let l = s.len();
+    let mut p_max = 0f64;
+    // (tuple id, start) of tuples occurring at least CUTOFF times,
+    // each iteration extends them by a sample.
+    let mut tuples: Vec<(usize, usize)> = (0..l).map(|i| (0, i)).collect();
+    for t in 1..l {
+        tuples.retain(|(_, i)| i + t <= l);
+        let mut ids = HashMap::new();
+        let mut counts: Vec<u64> = Vec::new();
+        for (id, i) in tuples.iter_mut() {
+            let next = ids.len();
+            *id = *ids.entry((*id, s[*i + t - 1])).or_insert(next);
+            if *id == counts.len() {
+                counts.push(0);
+            }
+            counts[*id] += 1;
+        }
+        let q = counts.iter().max().copied().unwrap_or(0);
+        if q < CUTOFF {
+            break;
+        }
+        let p = q as f64 / (l - t + 1) as f64;
+        p_max = p_max.max(p.powf(1.0 / t as f64));
+        tuples.retain(|(id, _)| counts[*id] >= CUTOFF);
+    }
+    -upper_bound(p_max, l).log2()
+}
+
+/// Lag Prediction estimate, bits per sample.
+///
+/// Ref 6.3.8
+pub fn lag_prediction(s: &[u8]) -> f64 {
+    const D: usize = 128;
+    let mut scoreboard = [0u64; D];
+    let mut winner = 0;
+    let mut correct = 0u64;
+    let mut run = 0u64;
+    let mut max_run = 0u64;
+
+    for i in 1..s.len() {
+        // subpredictor d predicts s[i - d - 1]
+        if winner < i && s[i - winner - 1] == s[i] {
+            correct += 1;
+            run += 1;
+            max_run = max_run.max(run);
+        } else {
+            run = 0;
+        }
+        for d in 0..D.min(i) {
+            if s[i - d - 1] == s[i] {
+                scoreboard[d] += 1;
+                if scoreboard[d] >= scoreboard[winner] {
+                    winner = d;
+                }
+            }
+        }
+    }
+
+    predictor_entropy(correct, s.len() - 1, max_run)
+}
+
+/// Min-entropy from predictor results, `correct` of `n` predictions.
+///
+/// Ref 6.3.7 steps 6-9
+fn predictor_entropy(correct: u64, n: usize, max_run: u64) -> f64 {
+    let p_global = if correct == 0 {
+        1.0 - 0.01f64.powf(1.0 / n as f64)
+    } else {
+        upper_bound(correct as f64 / n as f64, n)
+    };
+    let p_local = local_probability(n, max_run + 1);
+    -p_global.max(p_local).log2()
+}
+
+/// Success probability such that the longest run of correct predictions
+/// is shorter than `r` with probability 0.99.
+fn local_probability(n: usize, r: u64) -> f64 {
+    let r = r as i32;
+    let no_run = |p: f64| {
+        let q = 1.0 - p;
+        let mut x = 1.0f64;
+        for _ in 0..10 {
+            x = 1.0 + q * p.powi(r) * x.powi(r + 1);
+        }
+        (1.0 - p * x)
+            / ((r as f64 + 1.0 - r as f64 * x) * q)
+            / x.powf(n as f64 + 1.0)
+    };
+
+    // no_run() is decreasing in p
+    let (mut lo, mut hi) = (0.0f64, 1.0f64);
+    for _ in 0..64 {
+        let mid = (lo + hi) / 2.0;
+        if no_run(mid) > 0.99 {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// Returns the smallest `u` such that `P(X <= u) >= 1 - alpha`,
+/// for `X` binomially distributed with `n` trials of probability `p`.
+pub fn binomial_quantile(n: u64, p: f64, alpha: f64) -> u64 {
+    if p >= 1.0 {
+        return n;
+    }
+    // log space, (1-p)^n underflows for large n
+    let ratio = (p / (1.0 - p)).ln();
+    let mut log_pmf = n as f64 * (1.0 - p).ln();
+    let mut cdf = 0.0;
+    for k in 0..n {
+        cdf += log_pmf.exp();
+        if cdf >= 1.0 - alpha {
+            return k;
+        }
+        log_pmf += ((n - k) as f64 / (k + 1) as f64).ln() + ratio;
+    }
+    n
+}
+
+/// Min-entropy estimates, bits per sample.
+pub struct Estimates {
+    pub mcv: f64,
+    pub t_tuple: f64,
+    pub lag_prediction: f64,
+}
+
+impl Estimates {
+    /// Runs all estimators, `s` must have at least two samples.
+    pub fn new(s: &[u8]) -> Self {
+        Self { mcv: mcv(s), t_tuple: t_tuple(s), lag_prediction: lag_prediction(s) }
+    }
+
+    pub fn min(&self) -> f64 {
+        self.mcv.min(self.t_tuple).min(self.lag_prediction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudorandom bytes, xorshift32.
+    fn uniform(n: usize) -> Vec<u8> {
+        let mut x = 0x1234_5678u32;
+        (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                (x >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// `0, 1, 2, 0, 1, 2, ...`
+    fn periodic(n: usize) -> Vec<u8> {
+        (0..n).map(|i| (i % 3) as u8).collect()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn mcv_estimate() {
+        assert_eq!(mcv(&[7; 1000]), 0.0);
+
+        // p = 0.5 with n = 1000
+        let half: Vec<u8> = (0..1000).map(|i| (i % 2) as u8).collect();
+        assert!(close(mcv(&half), 0.8869649563897022));
+
+        // p = 1/256 with n = 25600
+        let all: Vec<u8> = (0..25600).map(|i| i as u8).collect();
+        assert!(close(mcv(&all), 7.669898970344335));
+
+        let e = mcv(&uniform(25600));
+        assert!(e > 7.0 && e < 8.0, "{e}");
+    }
+
+    #[test]
+    fn t_tuple_estimate() {
+        assert_eq!(t_tuple(&[7; 1000]), 0.0);
+
+        let e = t_tuple(&uniform(25600));
+        assert!(e > 7.0 && e < 8.0, "{e}");
+
+        // each value is equally common, but tuples repeat
+        let p = periodic(3000);
+        assert!(mcv(&p) > 1.4);
+        assert!(t_tuple(&p) < 0.1);
+    }
+
+    #[test]
+    fn lag_prediction_estimate() {
+        assert_eq!(lag_prediction(&[7; 1000]), 0.0);
+
+        let e = lag_prediction(&uniform(25600));
+        assert!(e > 7.0 && e < 8.0, "{e}");
+
+        let p = periodic(3000);
+        assert!(lag_prediction(&p) < 0.1);
+    }
+
+    #[test]
+    fn estimates_min() {
+        let e = Estimates::new(&periodic(3000));
+        assert_eq!(e.min(), e.t_tuple.min(e.lag_prediction));
+        assert!(e.min() < e.mcv);
+    }
+
+    #[test]
+    fn runs_test() {
+        let r = runs(&[0, 4, 0, 4, 0, 4, 2, 2, 2]);
+        assert_eq!(r.median, 2);
+        assert_eq!(r.runs, 6);
+        assert!(close(r.expected, 4.0));
+        assert!(close(r.z, 2.0 / 1.2f64.sqrt()));
+        assert_eq!(r.longest_repeat, 3);
+
+        let r = runs(&uniform(10000));
+        assert!(r.z.abs() < 3.0, "{}", r.z);
+        assert!(r.longest_repeat < 4);
+
+        assert_eq!(runs(&[5; 10]).longest_repeat, 10);
+    }
+
+    #[test]
+    fn autocorrelation_lags() {
+        // alternating, the lag k correlation is (-1)^k (n - k) / n
+        let s: Vec<u8> = (0..100).map(|i| (i % 2) as u8).collect();
+        let a = autocorrelation(&s, 3);
+        assert_eq!(a.len(), 3);
+        assert!(close(a[0], -0.99));
+        assert!(close(a[1], 0.98));
+        assert!(close(a[2], -0.97));
+
+        let a = autocorrelation(&periodic(3000), 3);
+        assert!(close(a[2], 2997.0 / 3000.0));
+
+        for c in autocorrelation(&uniform(10000), 8) {
+            assert!(c.abs() < 0.05, "{c}");
+        }
+    }
+
+    #[test]
+    fn lsb_chi2_counts() {
+        assert_eq!(lsb_chi2(&[800, 100, 100, 100, 100, 100]), 0.0);
+        assert!(close(lsb_chi2(&[500, 160, 160, 160, 160, 160]), 292.5));
+    }
+
+    #[test]
+    fn sample_helpers() {
+        assert_eq!(lsb(0x3e), 1);
+        assert_eq!(lsb(0), 8);
+        assert!(valid(0x3e));
+        assert!(!valid(0x3f));
+        assert!(timeout(0x1e));
+        assert!(!valid(0x1e));
+    }
+
+    #[test]
+    fn binomial_quantiles() {
+        assert_eq!(binomial_quantile(10, 0.5, 0.05), 8);
+        assert_eq!(binomial_quantile(10, 0.5, 0.5), 5);
+        assert_eq!(binomial_quantile(20, 0.1, 0.01), 6);
+        assert_eq!(binomial_quantile(100, 0.0, 0.01), 0);
+        assert_eq!(binomial_quantile(100, 1.0, 0.01), 100);
+        // (1-p)^n underflows
+        assert_eq!(binomial_quantile(1_000_000, 0.5, 0.01), 501_163);
+    }
+}
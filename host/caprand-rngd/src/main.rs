@@ -0,0 +1,202 @@
+//! Feeds a caprand USB device into the Linux entropy pool.
+//!
+//! Output is added to `/dev/random` with the `RNDADDENTROPY` ioctl (requires
+//! root), or written to stdout for `rngd`, for example
+//! `caprand-rngd --stdout | rngd -f -r /dev/stdin`.
+//!
+//! Device health counters are read after each chunk of output. If any health
+//! test failed since the previous check the chunk is discarded, and the
+//! program exits after repeated failures.
+//!
+//! In full entropy mode (the default) each output byte is credited with 8 bits.
+//! Conditioned DRBG output is only mixed in, not credited, since it
+//! holds no more entropy than the seed.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::process::ExitCode;
+
+use caprand_usb::{Counters, Device, Mode, RusbTransport, Stub, Transport};
+
+const USAGE: &str = "\
+Usage: caprand-rngd [options]
+
+Options:
+  --conditioned       Use conditioned DRBG output, not credited
+  --stdout            Write output to stdout rather than /dev/random
+  --count BYTES       Exit after BYTES of output
+  --max-failures N    Exit after N consecutive health failures, default 3
+  --stub              Use an in-process stub device, implies --stdout.
+                      Output is not random.
+  -h, --help          Show this help
+";
+
+/// `_IOW('R', 0x03, int[2])` from `linux/random.h`.
+const RNDADDENTROPY: libc::Ioctl = libc::_IOW::<[libc::c_int; 2]>(b'R' as u32, 0x03);
+
+/// Size of each chunk read from the device.
+const CHUNK: usize = 256;
+
+struct Args {
+    mode: Mode,
+    stdout: bool,
+    count: Option<u64>,
+    max_failures: u32,
+    stub: bool,
+}
+
+fn parse_args() -> Result<Args, pico_args::Error> {
+    let mut p = pico_args::Arguments::from_env();
+    if p.contains(["-h", "--help"]) {
+        print!("{USAGE}");
+        std::process::exit(0);
+    }
+    let mode = if p.contains("--conditioned") {
+        Mode::Conditioned
+    } else {
+        Mode::FullEntropy
+    };
+    let stub = p.contains("--stub");
+    let args = Args {
+        mode,
+        stdout: p.contains("--stdout") || stub,
+        count: p.opt_value_from_str("--count")?,
+        max_failures: p.opt_value_from_str("--max-failures")?.unwrap_or(3),
+        stub,
+    };
+    let rest = p.finish();
+    if !rest.is_empty() {
+        return Err(pico_args::Error::ArgumentParsingFailed {
+            cause: format!("unexpected arguments {rest:?}"),
+        });
+    }
+    Ok(args)
+}
+
+/// Destination for output.
+enum Sink {
+    Random(File),
+    Stdout(io::Stdout),
+}
+
+impl Sink {
+    fn add(&mut self, data: &[u8], bits: u32) -> io::Result<()> {
+        match self {
+            Self::Random(f) => add_entropy(f, data, bits),
+            Self::Stdout(s) => {
+                s.write_all(data)?;
+                s.flush()
+            }
+        }
+    }
+}
+
+/// Adds data to the kernel entropy pool, crediting `bits`.
+fn add_entropy(f: &File, data: &[u8], bits: u32) -> io::Result<()> {
+    // struct rand_pool_info {
+    //     int entropy_count;
+    //     int buf_size;
+    //     __u32 buf[0];
+    // };
+    let mut info = vec![0u32; 2 + data.len().div_ceil(4)];
+    info[0] = bits;
+    info[1] = data.len() as u32;
+    for (w, c) in info[2..].iter_mut().zip(data.chunks(4)) {
+        let mut b = [0u8; 4];
+        b[..c.len()].copy_from_slice(c);
+        *w = u32::from_ne_bytes(b);
+    }
+
+    // SAFETY: info is a valid rand_pool_info with buf_size bytes of buf.
+    let r = unsafe { libc::ioctl(f.as_raw_fd(), RNDADDENTROPY, info.as_ptr()) };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns whether the device is healthy since the `last` counters.
+fn healthy(last: &Counters, now: &Counters, mode: Mode) -> bool {
+    now.mode == mode && now.health_failures == last.health_failures
+}
+
+fn run<T: Transport>(
+    dev: &mut Device<T>,
+    args: &Args,
+    sink: &mut Sink,
+) -> Result<(), Box<dyn std::error::Error>> {
+    dev.set_mode(args.mode)?;
+    let mut last = dev.counters()?;
+
+    let mut buf = [0u8; CHUNK];
+    let mut total = 0u64;
+    let mut failures = 0;
+    while args.count.is_none_or(|c| total < c) {
+        let l = dev.read(&mut buf)?;
+        let now = dev.counters()?;
+        if !healthy(&last, &now, args.mode) {
+            failures += 1;
+            eprintln!("Health failure, discarding output ({now:?})");
+            if failures >= args.max_failures {
+                return Err("Too many health failures".into());
+            }
+            last = now;
+            continue;
+        }
+        failures = 0;
+        last = now;
+
+        let mut data = &buf[..l];
+        if let Some(c) = args.count {
+            data = &data[..data.len().min((c - total) as usize)];
+        }
+        let bits = match args.mode {
+            Mode::FullEntropy => data.len() as u32 * 8,
+            _ => 0,
+        };
+        sink.add(data, bits)?;
+        total += data.len() as u64;
+    }
+
+    dev.set_mode(Mode::Idle)?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut sink = if args.stdout {
+        Sink::Stdout(io::stdout())
+    } else {
+        match File::options().write(true).open("/dev/random") {
+            Ok(f) => Sink::Random(f),
+            Err(e) => {
+                eprintln!("Opening /dev/random: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+
+    let r = if args.stub {
+        run(&mut Device::new(Stub::new(1)), &args, &mut sink)
+    } else {
+        RusbTransport::open(caprand_usb::VID, caprand_usb::PID)
+            .map_err(Into::into)
+            .and_then(|t| run(&mut Device::new(t), &args, &mut sink))
+    };
+
+    match r {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Host library for the caprand USB vendor class entropy device.
+//!
+//! [`Device`] talks to the firmware's [`caprand::usb`] interface through a
+//! [`Transport`]. [`RusbTransport`] is used for real hardware (`rusb` feature,
+//! default), and [`Stub`] emulates a device in-process for testing host software.
+//!
+//! The protocol is described in [`caprand::usbproto`].
+//!
+//! ```no_run
+//! use caprand_usb::{Device, Mode, RusbTransport};
+//!
+//! let t = RusbTransport::open(caprand_usb::VID, caprand_usb::PID)?;
+//! let mut dev = Device::new(t);
+//! dev.set_mode(Mode::Raw)?;
+//! let frame = dev.read_frame()?;
+//! println!("{} samples from seq {}", frame.samples.len(), frame.seq);
+//! # Ok::<(), caprand_usb::Error>(())
+//! ```
+
+use std::fmt;
+
+use caprand::stream::{self, Continuity, Decoder, Metadata};
+pub use caprand::usbproto::{Counters, Mode};
+use caprand::usbproto::{REQ_GET_COUNTERS, REQ_SET_LOW_CYCLES, REQ_SET_MODE};
+
+mod stub;
+pub use stub::Stub;
+
+#[cfg(feature = "rusb")]
+mod rusb_transport;
+#[cfg(feature = "rusb")]
+pub use rusb_transport::RusbTransport;
+
+/// USB vendor id used by the `usbnoise` example firmware.
+pub const VID: u16 = 0x6666;
+/// USB product id used by the `usbnoise` example firmware.
+pub const PID: u16 = 0x5c4f;
+
+/// Size of bulk reads, a multiple of the packet size.
+const READ_SIZE: usize = 16384;
+
+/// Errors from the device.
+#[derive(Debug)]
+pub enum Error {
+    /// USB transport failure.
+    Transport(String),
+    /// Unexpected response from the device.
+    Protocol(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(e) => write!(f, "USB transport: {e}"),
+            Self::Protocol(e) => write!(f, "protocol: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Access to the device's vendor interface.
+///
+/// Control requests are vendor requests with interface recipient.
+pub trait Transport {
+    /// Sends an OUT control request without data.
+    fn control_out(&mut self, request: u8, value: u16) -> Result<()>;
+
+    /// Sends an IN control request, returning the length read into `buf`.
+    fn control_in(&mut self, request: u8, buf: &mut [u8]) -> Result<usize>;
+
+    /// Reads from the bulk IN endpoint, returning the length read.
+    ///
+    /// Returns `Ok(0)` on timeout, when the device has no output.
+    fn bulk_in(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// A raw mode frame read from the device.
+#[derive(Debug, Clone)]
+pub struct RawFrame {
+    pub meta: Metadata,
+    pub seq: u32,
+    /// Count of samples in prior frames of the session.
+    pub offset: u32,
+    pub samples: Vec<u8>,
+    /// Continuity with the previous frame read.
+    pub continuity: Continuity,
+}
+
+/// A caprand USB device.
+pub struct Device<T> {
+    t: T,
+    /// Bulk data not yet consumed.
+    buf: Vec<u8>,
+    decoder: Decoder,
+    /// Bytes skipped while resynchronising raw frames.
+    skipped: u64,
+}
+
+impl<T: Transport> Device<T> {
+    pub fn new(t: T) -> Self {
+        Self { t, buf: Vec::new(), decoder: Decoder::new(), skipped: 0 }
+    }
+
+    /// Sets the output mode.
+    ///
+    /// The device is first set idle and pending output is discarded, so that
+    /// subsequent reads only return output from the new mode. The next raw frame
+    /// starts a new session.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<()> {
+        self.t.control_out(REQ_SET_MODE, Mode::Idle as u16)?;
+        self.drain()?;
+        self.decoder = Decoder::new();
+        if mode != Mode::Idle {
+            self.t.control_out(REQ_SET_MODE, mode as u16)?;
+        }
+        Ok(())
+    }
+
+    /// Sets `low_cycles` for raw and health modes.
+    ///
+    /// Takes effect at the next block of samples.
+    pub fn set_low_cycles(&mut self, low_cycles: u16) -> Result<()> {
+        self.t.control_out(REQ_SET_LOW_CYCLES, low_cycles)
+    }
+
+    /// Reads the device counters.
+    pub fn counters(&mut self) -> Result<Counters> {
+        let mut b = [0u8; Counters::LEN];
+        let l = self.t.control_in(REQ_GET_COUNTERS, &mut b)?;
+        Counters::from_bytes(&b[..l]).ok_or(Error::Protocol("bad counters"))
+    }
+
+    /// Reads output into `out`, returning the length read.
+    ///
+    /// Used in [`Mode::Conditioned`] and [`Mode::FullEntropy`].
+    /// Returns `Ok(0)` on timeout.
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize> {
+        if !self.buf.is_empty() {
+            let l = out.len().min(self.buf.len());
+            out[..l].copy_from_slice(&self.buf[..l]);
+            self.buf.drain(..l);
+            return Ok(l);
+        }
+        self.t.bulk_in(out)
+    }
+
+    /// Reads the next raw frame, in [`Mode::Raw`].
+    ///
+    /// Corrupt data is skipped until a valid frame is found, see
+    /// [`skipped()`](Self::skipped).
+    pub fn read_frame(&mut self) -> Result<RawFrame> {
+        loop {
+            match stream::decode(&self.buf) {
+                Ok((f, len)) => {
+                    let frame = RawFrame {
+                        meta: f.meta.clone(),
+                        seq: f.seq,
+                        offset: f.offset,
+                        samples: f.samples.to_vec(),
+                        continuity: self.decoder.check(&f),
+                    };
+                    self.buf.drain(..len);
+                    return Ok(frame);
+                }
+                Err(stream::Error::Short) => self.fill()?,
+                Err(_) => {
+                    // Skip to the next possible frame start
+                    let skip = stream::find_magic(&self.buf[1..])
+                        .map(|p| p + 1)
+                        .unwrap_or(self.buf.len().saturating_sub(3));
+                    self.buf.drain(..skip);
+                    self.skipped += skip as u64;
+                }
+            }
+        }
+    }
+
+    /// Reads the next health counters record, in [`Mode::Health`].
+    pub fn read_health(&mut self) -> Result<Counters> {
+        while self.buf.len() < Counters::LEN {
+            self.fill()?;
+        }
+        let c = Counters::from_bytes(&self.buf);
+        self.buf.drain(..Counters::LEN);
+        c.ok_or(Error::Protocol("bad health record"))
+    }
+
+    /// Returns the number of bytes skipped while finding raw frames.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Returns the transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.t
+    }
+
+    /// Reads more bulk data into `buf`.
+    fn fill(&mut self) -> Result<()> {
+        let pos = self.buf.len();
+        self.buf.resize(pos + READ_SIZE, 0);
+        let r = self.t.bulk_in(&mut self.buf[pos..]);
+        let l = *r.as_ref().unwrap_or(&0);
+        self.buf.truncate(pos + l);
+        match r {
+            Ok(0) => Err(Error::Protocol("timeout")),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Discards output until the device is idle.
+    fn drain(&mut self) -> Result<()> {
+        self.buf.clear();
+        let mut b = vec![0u8; READ_SIZE];
+        while self.t.bulk_in(&mut b)? > 0 {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_framing() {
+        let mut dev = Device::new(Stub::new(1));
+        dev.set_mode(Mode::Raw).unwrap();
+        for seq in 0..3 {
+            let f = dev.read_frame().unwrap();
+            assert_eq!(f.seq, seq);
+            assert_eq!(f.offset, seq * 1024);
+            assert_eq!(f.samples.len(), 1024);
+            let expect = if seq == 0 {
+                Continuity::NewSession
+            } else {
+                Continuity::Contiguous
+            };
+            assert_eq!(f.continuity, expect);
+        }
+        assert_eq!(dev.skipped(), 0);
+        assert_eq!(dev.counters().unwrap().samples, 3 * 1024);
+    }
+
+    #[test]
+    fn mode_switch() {
+        let mut dev = Device::new(Stub::new(2));
+        dev.set_mode(Mode::Raw).unwrap();
+        dev.read_frame().unwrap();
+        dev.read_frame().unwrap();
+
+        dev.set_mode(Mode::Conditioned).unwrap();
+        assert_eq!(dev.counters().unwrap().mode, Mode::Conditioned);
+        let mut b = [0u8; 64];
+        assert_eq!(dev.read(&mut b).unwrap(), 64);
+        assert!(b.iter().any(|b| *b != 0));
+
+        // no conditioned output is mixed into frames
+        dev.set_mode(Mode::Raw).unwrap();
+        let f = dev.read_frame().unwrap();
+        assert_eq!(f.seq, 0);
+        assert_eq!(f.continuity, Continuity::NewSession);
+        assert_eq!(dev.skipped(), 0);
+
+        dev.set_low_cycles(7).unwrap();
+        assert_eq!(dev.counters().unwrap().low_cycles, 7);
+        assert_eq!(dev.read_frame().unwrap().meta.low_cycles, 7);
+
+        dev.set_mode(Mode::Idle).unwrap();
+        assert_eq!(dev.read(&mut b).unwrap(), 0);
+    }
+
+    #[test]
+    fn resync_after_corrupt() {
+        let mut dev = Device::new(Stub::new(3));
+        dev.set_mode(Mode::Raw).unwrap();
+        dev.read_frame().unwrap();
+
+        // corrupt a sample in the second frame
+        dev.transport().corrupt(100);
+        let f = dev.read_frame().unwrap();
+        assert!(dev.skipped() > 0);
+        assert_eq!(f.seq, 2);
+        assert_eq!(f.continuity, Continuity::Gap { frames: 1, samples: 1024 });
+
+        let f = dev.read_frame().unwrap();
+        assert_eq!(f.seq, 3);
+        assert_eq!(f.continuity, Continuity::Contiguous);
+    }
+
+    #[test]
+    fn health_failures() {
+        let mut dev = Device::new(Stub::new(4));
+        dev.transport().fail_every(2);
+        dev.set_mode(Mode::Health).unwrap();
+        let failures: Vec<u32> =
+            (0..4).map(|_| dev.read_health().unwrap().health_failures).collect();
+        assert_eq!(failures, [0, 1, 1, 2]);
+
+        // every block fails, so full entropy mode has no output
+        dev.transport().fail_every(1);
+        dev.set_mode(Mode::FullEntropy).unwrap();
+        let before = dev.counters().unwrap().health_failures;
+        let mut b = [0u8; 32];
+        assert_eq!(dev.read(&mut b).unwrap(), 0);
+        assert!(dev.counters().unwrap().health_failures > before);
+    }
+
+    #[test]
+    fn errors() {
+        let mut dev = Device::new(Stub::new(5));
+        // idle has no output
+        assert!(matches!(dev.read_frame(), Err(Error::Protocol("timeout"))));
+        assert!(matches!(dev.read_health(), Err(Error::Protocol("timeout"))));
+        // unknown mode stalls
+        assert!(matches!(
+            dev.transport().control_out(REQ_SET_MODE, 99),
+            Err(Error::Transport(_))
+        ));
+    }
+}
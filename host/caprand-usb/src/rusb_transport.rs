@@ -0,0 +1,95 @@
+//! Transport for real devices using `rusb`.
+
+use std::time::Duration;
+
+use caprand::usbproto::{CLASS, SUBCLASS};
+use rusb::{
+    ConfigDescriptor, DeviceHandle, Direction, GlobalContext, Recipient,
+    RequestType, TransferType,
+};
+
+use crate::{Error, Result, Transport};
+
+fn usb_err(e: rusb::Error) -> Error {
+    Error::Transport(e.to_string())
+}
+
+/// A USB device opened with `rusb`.
+pub struct RusbTransport {
+    handle: DeviceHandle<GlobalContext>,
+    iface: u8,
+    ep: u8,
+    timeout: Duration,
+}
+
+impl RusbTransport {
+    /// Opens the first device matching `vid` and `pid`, and claims
+    /// the caprand interface.
+    pub fn open(vid: u16, pid: u16) -> Result<Self> {
+        let handle = rusb::open_device_with_vid_pid(vid, pid)
+            .ok_or(Error::Transport("device not found".into()))?;
+        let config = handle.device().active_config_descriptor().map_err(usb_err)?;
+        let (iface, ep) = find_interface(&config)
+            .ok_or(Error::Protocol("no caprand interface"))?;
+        handle.set_auto_detach_kernel_driver(true).ok();
+        handle.claim_interface(iface).map_err(usb_err)?;
+        Ok(Self { handle, iface, ep, timeout: Duration::from_millis(500) })
+    }
+
+    /// Sets the timeout for transfers, default 500ms.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+/// Returns the interface number and bulk IN endpoint address.
+fn find_interface(config: &ConfigDescriptor) -> Option<(u8, u8)> {
+    for i in config.interfaces() {
+        for d in i.descriptors() {
+            if d.class_code() != CLASS || d.sub_class_code() != SUBCLASS {
+                continue;
+            }
+            for e in d.endpoint_descriptors() {
+                if e.direction() == Direction::In
+                    && e.transfer_type() == TransferType::Bulk
+                {
+                    return Some((d.interface_number(), e.address()));
+                }
+            }
+        }
+    }
+    None
+}
+
+impl Transport for RusbTransport {
+    fn control_out(&mut self, request: u8, value: u16) -> Result<()> {
+        let rt = rusb::request_type(
+            Direction::Out,
+            RequestType::Vendor,
+            Recipient::Interface,
+        );
+        self.handle
+            .write_control(rt, request, value, self.iface as u16, &[], self.timeout)
+            .map_err(usb_err)?;
+        Ok(())
+    }
+
+    fn control_in(&mut self, request: u8, buf: &mut [u8]) -> Result<usize> {
+        let rt = rusb::request_type(
+            Direction::In,
+            RequestType::Vendor,
+            Recipient::Interface,
+        );
+        self.handle
+            .read_control(rt, request, 0, self.iface as u16, buf, self.timeout)
+            .map_err(usb_err)
+    }
+
+    fn bulk_in(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.handle.read_bulk(self.ep, buf, self.timeout) {
+            Ok(l) => Ok(l),
+            Err(rusb::Error::Timeout) => Ok(0),
+            Err(e) => Err(usb_err(e)),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! An in-process emulation of the device.
+
+use std::collections::VecDeque;
+
+use caprand::stream::{frame_len, Encoder, Metadata, SourceKind};
+use caprand::usbproto::*;
+
+use crate::{Error, Result, Transport};
+
+/// Samples per raw mode frame, as the firmware.
+const RAW_SAMPLES: usize = 1024;
+
+/// Emulates the device protocol in-process, for testing host software.
+///
+/// Raw samples follow the shape of capacitor samples but come from a
+/// simple non-cryptographic generator, as does conditioned output.
+/// Output must not be used as entropy.
+///
+/// # Examples
+///
+/// ```
+/// use caprand_usb::{Device, Mode, Stub};
+///
+/// let mut dev = Device::new(Stub::new(1));
+/// dev.set_mode(Mode::Raw)?;
+/// let f = dev.read_frame()?;
+/// assert_eq!(f.samples.len(), 1024);
+/// assert_eq!(dev.counters()?.samples, 1024);
+/// # Ok::<(), caprand_usb::Error>(())
+/// ```
+pub struct Stub {
+    counters: Counters,
+    enc: Encoder,
+    meta: Metadata,
+    /// Bulk output not yet read.
+    pending: VecDeque<u8>,
+    state: u64,
+    /// Inject a health failure every `n` blocks.
+    fail_every: Option<u32>,
+    blocks: u32,
+}
+
+impl Stub {
+    /// Creates a stub, `seed` determines the output.
+    pub fn new(seed: u64) -> Self {
+        let meta = Metadata {
+            source: SourceKind::CapRise,
+            session: seed as u32,
+            clk_sys: 125_000_000,
+            low_cycles: 1,
+            pin: 10,
+            firmware: [0, 0, 0],
+            tag: *b"stub\0\0\0\0",
+        };
+        Self {
+            counters: Counters {
+                mode: Mode::Idle,
+                low_cycles: 1,
+                samples: 0,
+                valid: 0,
+                health_failures: 0,
+                bytes_sent: 0,
+            },
+            enc: Encoder::new(meta.clone()),
+            meta,
+            pending: VecDeque::new(),
+            state: seed | 1,
+            fail_every: None,
+            blocks: 0,
+        }
+    }
+
+    /// Reports a health test failure every `n` blocks of samples.
+    pub fn fail_every(&mut self, n: u32) {
+        self.fail_every = Some(n.max(1));
+    }
+
+    /// Corrupts pending bulk output, to exercise resynchronisation.
+    ///
+    /// If no output is pending, the next block is produced first.
+    pub fn corrupt(&mut self, pos: usize) {
+        if self.pending.is_empty() {
+            self.produce();
+        }
+        if let Some(b) = self.pending.get_mut(pos) {
+            *b ^= 0x55;
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // xorshift64
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// A sample shaped like `RawNoise` output, first set bit
+    /// at 0 with probability 8/13, otherwise 1 to 5.
+    fn sample(&mut self) -> u8 {
+        let k = match self.next_u64() % 13 {
+            0..=7 => 0,
+            r => r - 7,
+        };
+        (0x3f << k) & 0x3f
+    }
+
+    fn samples(&mut self) -> Vec<u8> {
+        let s: Vec<u8> = (0..RAW_SAMPLES).map(|_| self.sample()).collect();
+        // as caprand::cap::valid()
+        let valid = s.iter().filter(|v| *v & 1 == 0 && *v & 0x20 != 0).count();
+        self.blocks += 1;
+        let fail = self.fail_every.is_some_and(|n| self.blocks.is_multiple_of(n));
+
+        let c = &mut self.counters;
+        c.samples = c.samples.wrapping_add(s.len() as u32);
+        c.valid = c.valid.wrapping_add(valid as u32);
+        c.health_failures = c.health_failures.wrapping_add(fail as u32);
+        s
+    }
+
+    /// Produces the next block of output for the current mode.
+    fn produce(&mut self) {
+        let out = match self.counters.mode {
+            Mode::Idle => return,
+            Mode::Raw => {
+                let s = self.samples();
+                let mut f = vec![0u8; frame_len(s.len())];
+                // OK unwrap, frame is sized for samples
+                self.enc.encode(&s, &mut f).unwrap();
+                f
+            }
+            Mode::Conditioned => (0..MAX_PACKET / 8)
+                .flat_map(|_| self.next_u64().to_le_bytes())
+                .collect(),
+            Mode::Health => {
+                self.samples();
+                self.counters.to_bytes().to_vec()
+            }
+            Mode::FullEntropy => {
+                let failures = self.counters.health_failures;
+                self.samples();
+                if self.counters.health_failures != failures {
+                    // discarded block
+                    return;
+                }
+                (0..4).flat_map(|_| self.next_u64().to_le_bytes()).collect()
+            }
+        };
+        self.counters.bytes_sent =
+            self.counters.bytes_sent.wrapping_add(out.len() as u32);
+        self.pending.extend(out);
+    }
+
+    fn restart(&mut self) {
+        self.pending.clear();
+        let mut meta = self.meta.clone();
+        meta.low_cycles = self.counters.low_cycles as u32;
+        self.enc = Encoder::new(meta);
+    }
+}
+
+/// Packet size of the emulated bulk endpoint.
+const MAX_PACKET: usize = 64;
+
+impl Transport for Stub {
+    fn control_out(&mut self, request: u8, value: u16) -> Result<()> {
+        match request {
+            REQ_SET_MODE => {
+                self.counters.mode = Mode::try_from(value)
+                    .map_err(|_| Error::Transport("stall".into()))?;
+            }
+            REQ_SET_LOW_CYCLES => self.counters.low_cycles = value,
+            _ => return Err(Error::Transport("stall".into())),
+        }
+        self.restart();
+        Ok(())
+    }
+
+    fn control_in(&mut self, request: u8, buf: &mut [u8]) -> Result<usize> {
+        if request != REQ_GET_COUNTERS {
+            return Err(Error::Transport("stall".into()));
+        }
+        let c = self.counters.to_bytes();
+        let l = c.len().min(buf.len());
+        buf[..l].copy_from_slice(&c[..l]);
+        Ok(l)
+    }
+
+    fn bulk_in(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pending.is_empty() {
+            self.produce();
+        }
+        let l = buf.len().min(self.pending.len());
+        for (b, p) in buf.iter_mut().zip(self.pending.drain(..l)) {
+            *b = p;
+        }
+        Ok(l)
+    }
+}
@@ -2,4 +2,6 @@ MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
     FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
-}
\ No newline at end of file
+}
+/* The caprand `ram` feature places the timing loop in `.data.caprand`,
+   which link.x copies from FLASH to RAM at startup with other `.data` */
@@ -0,0 +1,37 @@
+#!/bin/sh
+# Checks that the timing loop is placed in RAM with the `ram` feature,
+# by reading the symbol table of an example.
+#
+# Usage: scripts/check-ram.sh [example]
+#
+# Requires llvm-nm, or set NM (for example NM=rust-nm from cargo-binutils).
+
+set -e
+cd "$(dirname "$0")/.."
+
+example=${1:-rand}
+nm=${NM:-llvm-nm}
+elf=target/thumbv6m-none-eabi/release/examples/$example
+
+cargo build --release --features defmt --example "$example"
+
+syms=$($nm --demangle --defined-only "$elf" | grep -E 'caprand::cap::(time_rise|time_fall|exact_drive)' || true)
+echo "$syms"
+
+if ! echo "$syms" | grep -q time_rise; then
+    echo "time_rise not found in $elf"
+    exit 1
+fi
+
+# exact_drive may be inlined into time_rise. RAM is 0x20000000
+echo "$syms" | while read -r addr _ name; do
+    case $addr in
+        2*) ;;
+        *)
+            echo "Not in RAM: $name at $addr"
+            exit 1
+            ;;
+    esac
+done
+
+echo "OK, timing loop is in RAM"
@@ -0,0 +1,108 @@
+//! A noise source using the RP2040 ADC.
+//!
+//! This is an alternative for boards without a capacitor fitted. The low bits of
+//! ADC conversions contain thermal noise, from either a floating or tied ADC input pin,
+//! or the internal temperature sensor.
+//!
+//! Most users should use [`caprand::setup`](crate::setup) with a capacitor instead,
+//! or [`caprand::setup_from_noise`](crate::setup_from_noise) with an [`AdcNoise`].
+#[cfg(not(feature = "defmt"))]
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+
+use embassy_rp::adc::{Adc, Blocking, Channel};
+use embassy_rp::peripherals::ADC;
+use embassy_rp::Peri;
+
+use crate::cap::Sample;
+use crate::source::NoiseSource;
+
+/// A noise source iterator using the low bits of ADC conversions.
+///
+/// Each sample is the low 6 bits of a 12-bit conversion. A conversion
+/// error yields [`AdcNoise::ERROR`], which is not a valid sample.
+///
+/// Samples are biased, so must be processed before further use
+/// (see [`CapRng`](crate::rng::CapRng)). This exposes the same `fill()`
+/// and [`Sample`] iterator interface as [`RawNoise`](crate::cap::RawNoise).
+///
+/// # Examples
+///
+/// ```
+/// use embassy_rp::adc::Channel;
+/// use embassy_rp::gpio::Pull;
+///
+/// let p = embassy_rp::init(Default::default());
+/// let ch = Channel::new_pin(p.PIN_26, Pull::None);
+/// let mut noise = caprand::adc::AdcNoise::new(p.ADC, ch);
+/// caprand::setup_from_noise(&mut noise, &Default::default()).unwrap();
+/// ```
+pub struct AdcNoise<'d> {
+    adc: Adc<'d, Blocking>,
+    ch: Channel<'d>,
+}
+
+impl<'d> AdcNoise<'d> {
+    /// Sample value for a failed conversion.
+    pub const ERROR: u8 = 0xff;
+
+    /// Creates a noise source reading from `ch`.
+    ///
+    /// `ch` may be an ADC input pin (without pulls) or the temperature sensor.
+    pub fn new(adc: Peri<'d, ADC>, ch: Channel<'d>) -> Self {
+        let adc = Adc::new_blocking(adc, Default::default());
+        Self { adc, ch }
+    }
+
+    fn sample(&mut self) -> u8 {
+        match self.adc.blocking_read(&mut self.ch) {
+            Ok(v) => (v & 0x3f) as u8,
+            Err(_) => {
+                trace!("ADC conversion failed");
+                Self::ERROR
+            }
+        }
+    }
+
+    /// Fills `buf` with samples.
+    ///
+    /// Each conversion takes 96 ADC clock cycles (2µs).
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for b in buf.iter_mut() {
+            *b = self.sample();
+        }
+    }
+
+    /// Fills `buf` with `(value, valid)` samples.
+    pub fn fill_with_validity(&mut self, buf: &mut [(u8, bool)]) {
+        for b in buf.iter_mut() {
+            let r = self.sample();
+            *b = (r, r != Self::ERROR);
+        }
+    }
+}
+
+impl NoiseSource for AdcNoise<'_> {
+    fn fill(&mut self, buf: &mut [u8]) {
+        AdcNoise::fill(self, buf)
+    }
+
+    fn valid(&self, v: u8) -> bool {
+        v != Self::ERROR
+    }
+}
+
+impl Iterator for AdcNoise<'_> {
+    /// `valid` is false for failed conversions. [`Sample::Timeout`] isn't
+    /// produced.
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let r = self.sample();
+        Some(Sample::Value(r, r != Self::ERROR))
+    }
+}
@@ -0,0 +1,237 @@
+//! Calibration of `low_cycles` for a capacitor.
+//!
+//! The time the pin is held low determines how far the capacitor discharges,
+//! and so the rise time. A longer rise time usually has more timing jitter,
+//! but seeding takes longer. The best setting depends on the capacitor value
+//! and clock.
+//!
+//! [`calibrate()`] sweeps [`CANDIDATES`], measuring rise times with
+//! [`RawNoise::next_with_systick()`]. The resulting [`Calibration`] can be
+//! persisted with [`Calibration::to_bytes()`], and passed back in
+//! [`Config::calibration`](crate::Config::calibration).
+
+#[cfg(not(feature = "defmt"))]
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+
+use embassy_rp::gpio::Pin;
+use embassy_rp::Peri;
+
+use crate::cap::{timeout, valid, with_systick, Mode, RawNoise};
+use crate::estimate::{mcv, OnlineMcv};
+use crate::pool::Estimate;
+use crate::source::NoiseSource;
+use crate::{Config, Error};
+
+/// `low_cycles` values tried by [`calibrate()`].
+pub const CANDIDATES: [u32; 10] = [1, 2, 3, 4, 6, 8, 12, 16, 24, 32];
+
+/// Rise times measured for each candidate.
+const SAMPLES: usize = 256;
+
+/// Cycles for each sampling loop.
+const LOOP_CYCLES: u32 = 13;
+
+/// Proportion of valid samples, in thirteenths.
+const VALID_13THS: u64 = 5;
+
+/// Maximum samples taken for an [`Estimate::Online`] window.
+const ONLINE_SAMPLES: usize = 16 * Estimate::WINDOW as usize;
+
+/// A calibrated `low_cycles` setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    pub low_cycles: u32,
+    /// Min-entropy estimate of the rise time jitter, in millibits per sample.
+    ///
+    /// This ranks candidates. It isn't comparable to the crediting estimate,
+    /// which measures the 6-bit sample values.
+    pub estimate: u32,
+    /// Median cycles for each sample.
+    pub sample_cycles: u32,
+    /// Predicted time to seed, in milliseconds.
+    pub seed_ms: u32,
+    /// `clk_sys` frequency when calibrated, in Hz.
+    pub clk_sys: u32,
+}
+
+impl Calibration {
+    /// Encoded length.
+    pub const LEN: usize = 24;
+
+    /// Encoding version.
+    const VERSION: u8 = 1;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut b = [0u8; Self::LEN];
+        b[0] = Self::VERSION;
+        // b[1..4] reserved
+        b[4..8].copy_from_slice(&self.low_cycles.to_le_bytes());
+        b[8..12].copy_from_slice(&self.estimate.to_le_bytes());
+        b[12..16].copy_from_slice(&self.sample_cycles.to_le_bytes());
+        b[16..20].copy_from_slice(&self.seed_ms.to_le_bytes());
+        b[20..24].copy_from_slice(&self.clk_sys.to_le_bytes());
+        b
+    }
+
+    /// Decodes a calibration, returning `None` if it is invalid.
+    pub fn from_bytes(b: &[u8]) -> Option<Self> {
+        let b: &[u8; Self::LEN] = b.get(..Self::LEN)?.try_into().ok()?;
+        if b[0] != Self::VERSION {
+            return None;
+        }
+        let le32 =
+            |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        let low_cycles = le32(4);
+        if low_cycles == 0 {
+            return None;
+        }
+        Some(Self {
+            low_cycles,
+            estimate: le32(8),
+            sample_cycles: le32(12),
+            seed_ms: le32(16),
+            clk_sys: le32(20),
+        })
+    }
+
+    /// Returns whether the calibration was made at the current `clk_sys`.
+    pub fn matches_clock(&self) -> bool {
+        self.clk_sys == embassy_rp::clocks::clk_sys_freq()
+    }
+}
+
+/// Finds a `low_cycles` setting for the capacitor on `pin`.
+///
+/// Picks the candidate with the highest entropy estimate that seeds within
+/// `budget_ms`, using the `mode` and crediting of `config`. With
+/// [`Estimate::Online`] a window of samples is measured for each candidate
+/// as seeding would. Fails with [`Error::RiseTooSlow`] if no candidate meets
+/// the budget.
+///
+/// SYST is used for timing, see [`RawNoise::check()`].
+pub fn calibrate(
+    mut pin: Peri<impl Pin>,
+    config: &Config,
+    budget_ms: u32,
+) -> Result<Calibration, Error> {
+    let clk_sys = embassy_rp::clocks::clk_sys_freq();
+    let mut best: Option<Calibration> = None;
+
+    for low_cycles in CANDIDATES {
+        let mut noise = RawNoise::new(pin.reborrow(), low_cycles);
+        noise.set_mode(config.mode);
+        let online = match config.estimate {
+            Some(Estimate::Online { .. }) => Some(online_estimate(&mut noise)?),
+            _ => None,
+        };
+        let c = with_systick(|syst| {
+            // first samples vary due to cache loads and the initial capacitor charge
+            for _ in 0..4 {
+                noise.next_with_systick(syst)?;
+            }
+            let mut t = [0u32; SAMPLES];
+            for t in t.iter_mut() {
+                *t = noise.next_with_systick(syst)?;
+            }
+            Ok(measure(&mut t, low_cycles, config, clk_sys, online))
+        })?;
+
+        debug!(
+            "low_cycles {} estimate {} sample_cycles {} seed_ms {}",
+            c.low_cycles, c.estimate, c.sample_cycles, c.seed_ms
+        );
+        if c.seed_ms > budget_ms {
+            continue;
+        }
+        if best.is_none_or(|b| c.estimate > b.estimate) {
+            best = Some(c);
+        }
+    }
+
+    best.ok_or(Error::RiseTooSlow)
+}
+
+/// Returns the [`OnlineMcv`] estimate of a window of valid samples, as
+/// credited by [`Estimate::Online`].
+///
+/// Returns 0 if a window isn't complete within [`ONLINE_SAMPLES`].
+fn online_estimate<P: Pin>(noise: &mut RawNoise<P>) -> Result<u32, Error> {
+    let mut mcv = OnlineMcv::new(Estimate::WINDOW);
+    let mut buf = [0u8; 64];
+    for _ in 0..ONLINE_SAMPLES / buf.len() {
+        noise.fill(&mut buf);
+        for v in buf {
+            if timeout(v) {
+                return Err(noise.timeout_error(v));
+            }
+            if valid(v) {
+                if let Some(e) = mcv.feed(v) {
+                    return Ok(e);
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Computes a calibration from rise times `t`.
+///
+/// `online` is the [`online_estimate()`] for [`Estimate::Online`].
+fn measure(
+    t: &mut [u32],
+    low_cycles: u32,
+    config: &Config,
+    clk_sys: u32,
+    online: Option<u32>,
+) -> Calibration {
+    // Jitter of the whole rise time between samples. The phase within the
+    // sampling loop has much the same distribution for any low_cycles,
+    // so can't distinguish candidates. With Mode::Both samples alternate
+    // between edges, each edge is estimated separately.
+    let estimate = if config.mode == Mode::Both {
+        let mut edges = [0u32; SAMPLES];
+        let (a, b) = edges.split_at_mut(t.len() / 2);
+        let (pairs, _) = t.as_chunks::<2>();
+        for (i, &[x, y]) in pairs.iter().enumerate() {
+            a[i] = x;
+            b[i] = y;
+        }
+        jitter(a).min(jitter(b))
+    } else {
+        jitter(t)
+    };
+
+    t.sort_unstable();
+    let sample_cycles = t[t.len() / 2];
+
+    // entropy credited per valid sample when seeding, in millibits
+    // times the divisor
+    let (credit, divisor) =
+        match config.estimate.unwrap_or(Estimate::for_clock(clk_sys)) {
+            Estimate::Fixed(m) => (m, 1),
+            Estimate::Online { divisor } => (online.unwrap_or(0), divisor.max(1)),
+        };
+    let seed_ms = if credit == 0 {
+        u32::MAX
+    } else {
+        let valid = config.seed_bits as u64 * 1000 * divisor as u64 / credit as u64;
+        let samples = valid * LOOP_CYCLES as u64 / VALID_13THS;
+        let ms = samples * sample_cycles as u64 * 1000 / clk_sys.max(1) as u64;
+        ms.min(u32::MAX as u64) as u32
+    };
+
+    Calibration { low_cycles, estimate, sample_cycles, seed_ms, clk_sys }
+}
+
+/// Returns the MCV estimate of rise times `t`, sorting `t`.
+fn jitter(t: &mut [u32]) -> u32 {
+    t.sort_unstable();
+    let max = t.chunk_by(|a, b| a == b).map(|r| r.len()).max().unwrap_or(0);
+    mcv(max as u32, t.len() as u32)
+}
@@ -2,6 +2,7 @@
 //!
 //! Most users should use [`caprand::setup`](crate::setup) and [`caprand::getrandom`](crate::getrandom) instead.
 //! This module is accessible for health testing and analysis.
+use cortex_m::peripheral::syst::SystClkSource;
 use cortex_m::peripheral::SYST;
 #[cfg(not(feature = "defmt"))]
 #[allow(unused_imports)]
@@ -16,16 +17,41 @@ use core::arch::asm;
 use embassy_rp::gpio::Pin;
 use embassy_rp::{pac, Peri};
 
+use crate::health::{LsbMonitor, Warmup};
+use crate::pool::Estimate;
+pub use crate::sample::{fall, lsb, timeout, valid, FALL};
+use crate::source::NoiseSource;
+use crate::Error;
+
 /// Drives a pin low for an exact number of cycles.
 ///
 /// Will be called with the pin output disabled.
 /// Call with interrupts disabled if it's important.
-fn exact_low<P: Pin>(pin: &Peri<P>, low_cycles: u32) {
+#[inline(always)]
+pub(crate) fn exact_low<P: Pin>(pin: &Peri<P>, low_cycles: u32) {
+    exact_drive(pin, false, low_cycles)
+}
+
+/// Drives a pin high for an exact number of cycles, as [`exact_low()`].
+#[inline(always)]
+fn exact_high<P: Pin>(pin: &Peri<P>, high_cycles: u32) {
+    exact_drive(pin, true, high_cycles)
+}
+
+/// Drives a pin to a level for an exact number of cycles.
+///
+/// Placed in RAM along with [`time_rise()`].
+#[cfg_attr(feature = "ram", link_section = ".data.caprand")]
+fn exact_drive<P: Pin>(pin: &Peri<P>, high: bool, cycles: u32) {
     let pin_num = pin.pin() as usize;
     let mask = 1u32 << pin_num;
-    // set pin value low. not out enabled yet
+    // set pin value. not out enabled yet
     let so = pac::SIO.gpio_out(0);
-    so.value_clr().write_value(1 << pin_num);
+    if high {
+        so.value_set().write_value(mask);
+    } else {
+        so.value_clr().write_value(mask);
+    }
 
     // get output-enable set/clear registers
     let soe = pac::SIO.gpio_oe(0);
@@ -34,7 +60,7 @@ fn exact_low<P: Pin>(pin: &Peri<P>, low_cycles: u32) {
 
     // We set output enable, wait a number of cycles, then clear output enable.
     // Modulo 3 because the subs/bne loops take 3 cycles.
-    match (low_cycles, low_cycles % 3) {
+    match (cycles, cycles % 3) {
         (0, _) => {
             // no drive low
         }
@@ -80,7 +106,7 @@ fn exact_low<P: Pin>(pin: &Peri<P>, low_cycles: u32) {
             mask = in(reg) mask,
             soe_set = in(reg) soe_set,
             soe_clr = in(reg) soe_clr,
-            d = in(reg) low_cycles,
+            d = in(reg) cycles,
             options(nostack, readonly),
             );
         },
@@ -102,7 +128,7 @@ fn exact_low<P: Pin>(pin: &Peri<P>, low_cycles: u32) {
             mask = in(reg) mask,
             soe_set = in(reg) soe_set,
             soe_clr = in(reg) soe_clr,
-            d = in(reg) low_cycles,
+            d = in(reg) cycles,
             options(nostack, readonly),
             );
         },
@@ -125,7 +151,7 @@ fn exact_low<P: Pin>(pin: &Peri<P>, low_cycles: u32) {
             mask = in(reg) mask,
             soe_set = in(reg) soe_set,
             soe_clr = in(reg) soe_clr,
-            d = in(reg) low_cycles,
+            d = in(reg) cycles,
             options(nostack, readonly),
             );
         },
@@ -166,11 +192,21 @@ fn time_rise_noasm(pin: &mut impl Pin, _low_cycles: u32) -> u8 {
 /// Drives a pin low then times how long it takes to rise to logic high.
 ///
 /// `low_cycles` is the amount of time to hold the pin low to discharge
-/// the capacitor. As the pullup charges the capacitor, it samples on every
-/// clock cycles in bursts of 6 bits. When the final bit of a burst is high,
-/// it returns that 5-bit burst as the value (ignoring last bit which is always
-/// high)
-fn time_rise<P: Pin>(pin: &Peri<P>, low_cycles: u32) -> u8 {
+/// the capacitor. As the pullup charges the capacitor, it samples 6
+/// consecutive cycles of each 13 cycle loop. When the final bit of a burst is
+/// high, it returns that 5-bit burst as the value (ignoring last bit which is
+/// always high)
+///
+/// If the pin doesn't rise within `max_loops` iterations the final bit is
+/// clear, see [`timeout()`]. `max_loops` must be non-zero.
+///
+/// With the `ram` feature (default) this runs from RAM, copied from flash
+/// with `.data` at startup, so that flash XIP cache misses don't affect timing.
+/// It must not be inlined into a caller in flash.
+#[cfg_attr(feature = "ram", inline(never))]
+#[cfg_attr(feature = "ram", link_section = ".data.caprand")]
+fn time_rise<P: Pin>(pin: &Peri<P>, low_cycles: u32, max_loops: u32) -> u8 {
+    debug_assert!(max_loops > 0);
     let pin_num = pin.pin() as usize;
     let mask = 1u32 << pin_num;
 
@@ -189,6 +225,7 @@ fn time_rise<P: Pin>(pin: &Peri<P>, low_cycles: u32) -> u8 {
     let x2: u32;
     let x3: u32;
     let x4: u32;
+    let x5: u32;
     // Time how long it takes for the pullup to reach high signal level
     unsafe {
         asm!(
@@ -204,8 +241,18 @@ fn time_rise<P: Pin>(pin: &Peri<P>, low_cycles: u32) -> u8 {
             "ldr r7,   [{gpio_in}]",
             // only test the most recent sample. 1 cycle
             "ands r7, {mask}",
-            // Loop if bit set, 2 cycles
-            "beq 222b",
+            // Exit if bit set, 1 cycle not taken
+            "bne 333f",
+            // Decrement the loop counter in r11, 3 cycles. All low
+            // registers are in use, so it can't be a single subs.
+            "mov r7, r11",
+            "subs r7, 1",
+            "mov r11, r7",
+            // Loop if non-zero, 2 cycles
+            "bne 222b",
+            "333:",
+            // r7 is the final sample, or zero on timeout
+            "mov r11, r7",
             // restore
             "mov r7, r10",
             mask = in(reg) mask,
@@ -215,19 +262,20 @@ fn time_rise<P: Pin>(pin: &Peri<P>, low_cycles: u32) -> u8 {
             x2 = out(reg) x2,
             x3 = out(reg) x3,
             x4 = out(reg) x4,
+            inout("r11") max_loops => x5,
             out("r10") _,
             options(nostack, readonly),
         );
     }
 
-    // A loop takes 9 cycles, so we would expect the distribution of
+    // A loop takes 13 cycles, so we would expect the distribution of
     // first-bit-set to be:
-    // 0 4/9
-    // 1 1/9
-    // 2 1/9
-    // 3 1/9
-    // 4 1/9
-    // 5 1/9
+    // 0 8/13
+    // 1 1/13
+    // 2 1/13
+    // 3 1/13
+    // 4 1/13
+    // 5 1/13
 
     // Combine all measurements in a constant-time way
     let result = (x0 & mask)
@@ -235,8 +283,8 @@ fn time_rise<P: Pin>(pin: &Peri<P>, low_cycles: u32) -> u8 {
         | (x2 & mask).rotate_left(2)
         | (x3 & mask).rotate_left(3)
         | (x4 & mask).rotate_left(4)
-        // r7 register is always set on exit from the loop. 
-        | mask.rotate_left(5);
+        // x5 is set on exit from the loop, unless timed out.
+        | x5.rotate_left(5);
 
     let result = result.rotate_right(pin_num as u32) as u8;
 
@@ -247,37 +295,155 @@ fn time_rise<P: Pin>(pin: &Peri<P>, low_cycles: u32) -> u8 {
     result
 }
 
-/// Returns the least significant bit set, or 8 if 0.
+/// Drives a pin high then times how long it takes to fall to logic low.
 ///
-/// Is neither constant time nor efficient, for display purposes only.
-pub fn lsb(v: u8) -> u8 {
-    for i in 0..u8::BITS {
-        if v & 1 << i != 0 {
-            return i as u8;
-        }
+/// The counterpart of [`time_rise()`], with the capacitor charged for
+/// `high_cycles` then discharged through the pad's pulldown. The result
+/// has the same format, the first sample reading low is the lowest set bit
+/// and the final bit is clear on a timeout. [`FALL`] is not set.
+#[cfg_attr(feature = "ram", inline(never))]
+#[cfg_attr(feature = "ram", link_section = ".data.caprand")]
+fn time_fall<P: Pin>(pin: &Peri<P>, high_cycles: u32, max_loops: u32) -> u8 {
+    debug_assert!(max_loops > 0);
+    let pin_num = pin.pin() as usize;
+    let mask = 1u32 << pin_num;
+
+    let pad = pac::PADS_BANK0.gpio(pin_num);
+    // bank 0 single cycle IO in
+    let gpio_in = pac::SIO.gpio_in(0).as_ptr();
+    let gpio_out = pac::SIO.gpio_out(0);
+    let out_high = gpio_out.value().read() & mask != 0;
+
+    // enable pulldown
+    pad.modify(|s| s.set_pde(true));
+
+    // Drive high for a number of cycles
+    exact_high(pin, high_cycles);
+
+    let x0: u32;
+    let x1: u32;
+    let x2: u32;
+    let x3: u32;
+    let x4: u32;
+    let x5: u32;
+    // Time how long it takes for the pulldown to reach low signal level.
+    // The same loop as time_rise(), 13 cycles.
+    unsafe {
+        asm!(
+            // save (rust asm doesn't handle frame pointer r7)
+            "mov r10, r7",
+            "222:",
+            // read gpio_in register, 6 cycles
+            "ldr {x0}, [{gpio_in}]",
+            "ldr {x1}, [{gpio_in}]",
+            "ldr {x2}, [{gpio_in}]",
+            "ldr {x3}, [{gpio_in}]",
+            "ldr {x4}, [{gpio_in}]",
+            "ldr r7,   [{gpio_in}]",
+            // only test the most recent sample. 1 cycle
+            "ands r7, {mask}",
+            // Exit if bit clear, 1 cycle not taken
+            "beq 333f",
+            // Decrement the loop counter in r11, 3 cycles. All low
+            // registers are in use, so it can't be a single subs.
+            "mov r7, r11",
+            "subs r7, 1",
+            "mov r11, r7",
+            // Loop if non-zero, 2 cycles
+            "bne 222b",
+            // timed out, r11 is zero
+            "b 444f",
+            "333:",
+            // the pin fell
+            "mov r11, {mask}",
+            "444:",
+            // restore
+            "mov r7, r10",
+            mask = in(reg) mask,
+            gpio_in = in(reg) gpio_in,
+            x0 = out(reg) x0,
+            x1 = out(reg) x1,
+            x2 = out(reg) x2,
+            x3 = out(reg) x3,
+            x4 = out(reg) x4,
+            inout("r11") max_loops => x5,
+            out("r10") _,
+            options(nostack, readonly),
+        );
     }
-    8
+
+    // Samples are inverted so that the first low sample is set, as time_rise()
+    let result = (!x0 & mask)
+        | (!x1 & mask).rotate_left(1)
+        | (!x2 & mask).rotate_left(2)
+        | (!x3 & mask).rotate_left(3)
+        | (!x4 & mask).rotate_left(4)
+        // x5 is set on exit from the loop, unless timed out.
+        | x5.rotate_left(5);
+
+    let result = result.rotate_right(pin_num as u32) as u8;
+
+    // Disable pulldown until next run
+    pad.modify(|s| s.set_pde(false));
+
+    // exact_high() leaves the output value high
+    if !out_high {
+        gpio_out.value_clr().write_value(mask);
+    }
+
+    result
+}
+
+/// Which capacitor edges [`RawNoise`] times.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Discharge briefly, time the rise through the pullup.
+    #[default]
+    Rise,
+    /// Charge briefly, time the fall through the pulldown.
+    Fall,
+    /// Alternate rise and fall samples, doubling the sample rate.
+    ///
+    /// Each edge is a separate measurement, so health tests
+    /// are run separately for each.
+    Both,
+}
+
+/// A sample from the [`RawNoise`] or [`AdcNoise`](crate::adc::AdcNoise) iterators.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Sample {
+    /// A sample value, and whether it is [`valid()`].
+    Value(u8, bool),
+    /// The pin didn't rise within [`RawNoise::MAX_LOOPS`].
+    Timeout,
 }
 
 /// Wraps timing with SYST. The clock source must already be configured.
 struct SyTi<'t> {
     syst: &'t mut SYST,
     t1: u32,
+    // cycles per tick for the external reference
+    scale: Option<u32>,
 }
 
 impl<'t> SyTi<'t> {
-    /// panics if `syst` is not using the core clock.
+    /// `syst` may use the core clock, or the 1MHz external reference
+    /// (watchdog tick) which has microsecond resolution.
     fn new(syst: &'t mut SYST) -> Self {
-        assert!(
-            syst.get_clock_source()
-                == cortex_m::peripheral::syst::SystClkSource::Core
-        );
+        let scale = match syst.get_clock_source() {
+            SystClkSource::Core => None,
+            SystClkSource::External => {
+                Some(embassy_rp::clocks::clk_sys_freq() / REF_TICK_HZ)
+            }
+        };
         syst.clear_current();
         syst.enable_counter();
-        Self { syst, t1: SYST::get_reload() }
+        Self { syst, t1: SYST::get_reload(), scale }
     }
 
-    /// returns the duration, or failure on overflow
+    /// returns the duration in core clock cycles, or failure on overflow
     fn done(self) -> Result<u32, ()> {
         let t2 = SYST::get_current();
         if self.syst.has_wrapped() {
@@ -285,61 +451,364 @@ impl<'t> SyTi<'t> {
             return Err(());
         }
         self.syst.disable_counter();
-        Ok(self.t1 - t2)
+        let t = self.t1 - t2;
+        Ok(self.scale.map_or(t, |s| t.saturating_mul(s)))
     }
 }
 
+/// Frequency of the SYST external reference, the watchdog tick
+/// as configured by embassy-rp.
+const REF_TICK_HZ: u32 = 1_000_000;
+
+/// Runs `f` with SYST configured to count core clock cycles.
+///
+/// SYST configuration is restored afterwards, though the current count is lost.
+pub(crate) fn with_systick<R>(f: impl FnOnce(&mut SYST) -> R) -> R {
+    // SAFETY: SYST is only used within this function, and the
+    // configuration is restored.
+    let mut syst = unsafe { cortex_m::Peripherals::steal() }.SYST;
+    let source = syst.get_clock_source();
+    let reload = SYST::get_reload();
+    let enabled = syst.is_counter_enabled();
+    let interrupt = syst.is_interrupt_enabled();
+
+    syst.disable_interrupt();
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(0x00ff_ffff);
+
+    let r = f(&mut syst);
+
+    syst.set_clock_source(source);
+    syst.set_reload(reload);
+    syst.clear_current();
+    if interrupt {
+        syst.enable_interrupt();
+    }
+    if enabled {
+        syst.enable_counter();
+    }
+    r
+}
+
+/// Samples following warm-up measured by [`RawNoise::warm_up()`] for comparison.
+const WARMUP_REFERENCE: usize = 15;
+
 /// A noise source iterator using a capacitor on a GPIO pin.
 ///
 /// For each output sample it drives a pin low, then times
 /// how long it takes to rise to logic high. `low_cycles` is the amount of
 /// time to hold the pin low to discharge the capacitor.
-/// As the pullup charges the capacitor, it samples on every
-/// clock cycle in bursts of 6 cycles. When the final bit of a burst is high,
-/// it outputs that 6-bit burst as the value.
+/// As the pullup charges the capacitor, it samples 6 consecutive cycles of
+/// each 13 cycle loop. When the final bit of a burst is high, it outputs that
+/// 6-bit burst as the value. A rise during the other 7 cycles sets the first
+/// bit, so the sample isn't [`valid()`].
 ///
 /// Samples are correlated and biased, so must be processed before
 /// further use, using a cryptographic extractor or similar scheme
 /// (see [`CapRng`](crate::rng::CapRng))
 ///
+/// If the pin doesn't rise within [`MAX_LOOPS`](Self::MAX_LOOPS) the sample
+/// is a timeout, so a shorted pin can't hang with interrupts disabled.
+///
 /// It is advisable to collect output from RawNoise into a buffer and discard
-/// the first couple of samples - time will vary due to XIP cache loads from flash,
-/// as well as charge time varying for the capacitor's first cycle.
+/// the first samples with [`warm_up()`](Self::warm_up) - charge time varies for
+/// the capacitor's first cycle, as well as cache loads for code around the timing loop.
+///
+/// [`set_mode()`](Self::set_mode) can instead time the fall through the pad's pulldown,
+/// charging the capacitor high for `low_cycles`, or alternate both edges.
+/// Fall samples have [`FALL`] set.
 pub struct RawNoise<'a, P: Pin> {
     pin: Peri<'a, P>,
     low_cycles: u32,
+    clk_sys: u32,
+    mode: Mode,
+    // the last sample was a fall, for Mode::Both
+    fell: bool,
+    // indexed by fall()
+    monitor: [LsbMonitor; 2],
     _setup: PinSetup,
 }
 
 impl<'a, P: Pin> RawNoise<'a, P> {
+    /// Sample value for a [`timeout()`].
+    pub const TIMEOUT: u8 = 0;
+
+    /// Maximum loop iterations for a sample, around 1.3 million cycles.
+    ///
+    /// A sample that doesn't rise in time is a timeout.
+    pub const MAX_LOOPS: u32 = 100_000;
+
+    /// Rise times below this indicate no capacitor is fitted, at 125MHz.
+    ///
+    /// This includes measurement overhead of around 30 cycles.
+    /// [`check()`](Self::check) scales it for other clocks.
+    pub const MIN_RISE_CYCLES: u32 = 64;
+
+    /// Rise times above this are too slow for sampling, 1ms at 125MHz.
+    ///
+    /// [`check()`](Self::check) scales it for other clocks.
+    pub const MAX_RISE_CYCLES: u32 = 125_000;
+
+    /// Samples discarded by [`check()`](Self::check).
+    const CHECK_WARMUP: usize = 4;
+
     pub fn new(pin: Peri<'a, P>, low_cycles: u32) -> Self {
         let setup = PinSetup::new(pin.pin());
-        Self { pin, low_cycles, _setup: setup }
+        let clk_sys = embassy_rp::clocks::clk_sys_freq();
+        crate::health::record_clk_sys(clk_sys);
+        Self {
+            pin,
+            low_cycles,
+            clk_sys,
+            mode: Mode::Rise,
+            fell: false,
+            monitor: Default::default(),
+            _setup: setup,
+        }
+    }
+
+    /// Sets which edges are timed, the default is [`Mode::Rise`].
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Returns which edges are timed.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns the `clk_sys` frequency in Hz, read when created.
+    ///
+    /// Samples resolve a single cycle, so noise per sample depends on the clock.
+    pub fn clk_sys(&self) -> u32 {
+        self.clk_sys
+    }
+
+    /// Returns the distribution monitor for rise or [`fall()`] samples from this source.
+    ///
+    /// All samples are monitored. Results are also recorded in
+    /// [`health_stats()`](crate::health_stats).
+    pub fn lsb_monitor(&self, fall: bool) -> &LsbMonitor {
+        &self.monitor[fall as usize]
+    }
+
+    fn monitor(&mut self, v: u8) {
+        // Anomalies are recorded in health stats
+        let _ = self.monitor[fall(v) as usize].feed(v);
+    }
+
+    /// Times the next edge for the mode.
+    ///
+    /// Call with interrupts disabled.
+    fn sample(&mut self) -> u8 {
+        let fall = match self.mode {
+            Mode::Rise => false,
+            Mode::Fall => true,
+            Mode::Both => !self.fell,
+        };
+        self.fell = fall;
+        if fall {
+            time_fall(&self.pin, self.low_cycles, Self::MAX_LOOPS) | FALL
+        } else {
+            time_rise(&self.pin, self.low_cycles, Self::MAX_LOOPS)
+        }
     }
 
     /// Returns the next sample as a total cycle count.
     ///
     /// This cycle count is only relative for comparison between samples.
-    pub fn next_with_systick(&mut self, syst: &mut SYST) -> Result<u32, ()> {
+    /// It includes some measurement overhead.
+    ///
+    /// Fails with [`Error::PinStuckLow`] if the pin doesn't rise within
+    /// [`MAX_LOOPS`](Self::MAX_LOOPS), or [`Error::RiseTooSlow`] if `syst` wraps.
+    /// Falls are timed as for [`fill()`](Self::fill), failing with [`Error::PinStuckHigh`].
+    pub fn next_with_systick(&mut self, syst: &mut SYST) -> Result<u32, Error> {
         critical_section::with(|_cs| {
             let t = SyTi::new(syst);
-            let r = time_rise(&self.pin, self.low_cycles);
-            let t = t.done()?;
+            let r = self.sample();
+            let t = t.done().map_err(|_| Error::RiseTooSlow)?;
+            if timeout(r) {
+                return Err(if fall(r) {
+                    Error::PinStuckHigh
+                } else {
+                    Error::PinStuckLow
+                });
+            }
             let t = t + lsb(r) as u32;
             Ok(t)
         })
     }
+
+    /// Checks that a capacitor is fitted and rises within the expected time.
+    ///
+    /// Returns the typical rise time in cycles from [`next_with_systick()`](Self::next_with_systick).
+    /// This borrows SYST, restoring its configuration afterwards. The current count
+    /// is lost, so SYST must not be in use for timing.
+    pub fn check(&mut self) -> Result<u32, Error> {
+        let w = self.warm_up(Self::CHECK_WARMUP)?;
+        self.check_rise(w.median)
+    }
+
+    /// Checks a rise time from [`warm_up()`](Self::warm_up), as [`check()`](Self::check).
+    ///
+    /// With [`Mode::Both`] the median mixes rise and fall times. Both edges
+    /// charge the same capacitor so have similar times, and a stuck edge
+    /// fails with a timeout while warming up instead.
+    pub(crate) fn check_rise(&self, rise: u32) -> Result<u32, Error> {
+        debug!("Rise time {} cycles", rise);
+        // thresholds are in absolute time
+        let scale = |c: u32| {
+            (c as u64 * self.clk_sys as u64 / Estimate::REFERENCE_CLK as u64) as u32
+        };
+        if rise < scale(Self::MIN_RISE_CYCLES) {
+            Err(Error::NoCapacitor)
+        } else if rise > scale(Self::MAX_RISE_CYCLES) {
+            Err(Error::RiseTooSlow)
+        } else {
+            Ok(rise)
+        }
+    }
+
+    /// Discards the first samples, measuring how they differ from later samples.
+    ///
+    /// Rise times of `discard` samples are measured, then compared with
+    /// 15 following samples. None are output. The result is also recorded in [`health_stats()`](crate::health_stats).
+    /// With [`Mode::Both`] rise and fall times are both included.
+    ///
+    /// This borrows SYST, as [`check()`](Self::check).
+    pub fn warm_up(&mut self, discard: usize) -> Result<Warmup, Error> {
+        let (first, lo, hi, mut t) = with_systick(|syst| {
+            let mut first = None;
+            let (mut lo, mut hi) = (u32::MAX, 0);
+            for _ in 0..discard {
+                let t = self.next_with_systick(syst)?;
+                first.get_or_insert(t);
+                lo = lo.min(t);
+                hi = hi.max(t);
+            }
+            let mut t = [0u32; WARMUP_REFERENCE];
+            for t in t.iter_mut() {
+                *t = self.next_with_systick(syst)?;
+            }
+            Ok::<_, Error>((first, lo, hi, t))
+        })?;
+
+        t.sort_unstable();
+        let median = t[t.len() / 2];
+        let w = Warmup {
+            discarded: discard as u32,
+            median,
+            spread: t[t.len() - 1] - t[0],
+            first_deviation: first.map_or(0, |f| f.abs_diff(median)),
+            max_deviation: if discard > 0 {
+                lo.abs_diff(median).max(hi.abs_diff(median))
+            } else {
+                0
+            },
+        };
+        debug!(
+            "Warm-up {} samples, deviation first {} max {}, spread {}",
+            w.discarded, w.first_deviation, w.max_deviation, w.spread
+        );
+        crate::health::record_warmup(w);
+        Ok(w)
+    }
+
+    /// Fills `buf` with samples.
+    ///
+    /// All samples are collected inside a single critical section, so
+    /// interrupts will be disabled for the duration.
+    /// [`valid()`] can be used to check each sample.
+    ///
+    /// After a [`timeout()`] the remainder of `buf` is filled with
+    /// [`TIMEOUT`](Self::TIMEOUT) without sampling.
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        let n = critical_section::with(|_cs| {
+            let mut n = 0;
+            let mut it = buf.iter_mut();
+            for b in it.by_ref() {
+                *b = self.sample();
+                n += 1;
+                if timeout(*b) {
+                    break;
+                }
+            }
+            it.for_each(|b| *b = Self::TIMEOUT);
+            n
+        });
+        // filler after a timeout wasn't sampled
+        for b in buf[..n].iter() {
+            self.monitor(*b);
+        }
+    }
+
+    /// Fills `buf` with `(value, valid)` samples, as returned by the iterator.
+    ///
+    /// All samples are collected inside a single critical section.
+    pub fn fill_with_validity(&mut self, buf: &mut [(u8, bool)]) {
+        let n = critical_section::with(|_cs| {
+            let mut n = 0;
+            let mut it = buf.iter_mut();
+            for b in it.by_ref() {
+                let r = self.sample();
+                *b = (r, valid(r));
+                n += 1;
+                if timeout(r) {
+                    break;
+                }
+            }
+            it.for_each(|b| *b = (Self::TIMEOUT, false));
+            n
+        });
+        for (b, _) in buf[..n].iter() {
+            self.monitor(*b);
+        }
+    }
+}
+
+impl<P: Pin> NoiseSource for RawNoise<'_, P> {
+    fn fill(&mut self, buf: &mut [u8]) {
+        RawNoise::fill(self, buf)
+    }
+
+    fn valid(&self, v: u8) -> bool {
+        valid(v)
+    }
+
+    fn timeout(&self, v: u8) -> bool {
+        timeout(v)
+    }
+
+    fn timeout_error(&self, v: u8) -> Error {
+        if fall(v) {
+            Error::PinStuckHigh
+        } else {
+            Error::PinStuckLow
+        }
+    }
+
+    fn channel(&self, v: u8) -> usize {
+        fall(v) as usize
+    }
+
+    fn estimate(&self) -> Estimate {
+        Estimate::for_clock(self.clk_sys)
+    }
 }
 
 impl<P: Pin> Iterator for RawNoise<'_, P> {
-    /// (`value, valid)`. `valid` is false for samples that are the first
+    /// `valid` is false for samples that are the first
     /// of a sequence, to simplify health checks.
-    type Item = (u8, bool);
+    type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let r = critical_section::with(|_cs| time_rise(&self.pin, self.low_cycles));
-        let valid = (r & 1) == 0;
-        Some((r, valid))
+        let r = critical_section::with(|_cs| self.sample());
+        self.monitor(r);
+        if timeout(r) {
+            Some(Sample::Timeout)
+        } else {
+            Some(Sample::Value(r, valid(r)))
+        }
     }
 }
 
@@ -347,7 +816,7 @@ impl<P: Pin> Iterator for RawNoise<'_, P> {
 ///
 /// This is equivalent to setup performed by embassy-rp HAL, but
 /// works with a borrowed PAC pin that can be re-used later by the application.
-struct PinSetup {
+pub(crate) struct PinSetup {
     pin: u8,
     // previous values to restore
     schmitt: bool,
@@ -358,7 +827,7 @@ struct PinSetup {
 }
 
 impl PinSetup {
-    fn new(pin_num: u8) -> Self {
+    pub(crate) fn new(pin_num: u8) -> Self {
         let (schmitt, ie, pde, pue) =
             pac::PADS_BANK0.gpio(pin_num as usize).modify(|s| {
                 let prev = (s.schmitt(), s.ie(), s.pde(), s.pue());
@@ -0,0 +1,199 @@
+//! Rise time characterisation, for qualifying capacitors.
+//!
+//! [`Characterize`] accumulates rise times into a histogram, with summary
+//! statistics and the autocorrelation of consecutive samples. On a device rise
+//! times are measured by [`capture()`], using
+//! [`RawNoise::next_with_systick()`](crate::cap::RawNoise::next_with_systick).
+//! Captured rise times can also be analysed on a host
+//! (build with `default-features = false`).
+//!
+//! Integer arithmetic is used, the RP2040 has no floating point unit.
+
+#[cfg(feature = "rp2040")]
+use embassy_rp::gpio::Pin;
+
+#[cfg(feature = "rp2040")]
+use crate::cap::{with_systick, RawNoise};
+#[cfg(feature = "rp2040")]
+use crate::Error;
+
+/// Summary statistics of rise times, in cycles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Summary {
+    pub n: u32,
+    pub min: u32,
+    pub max: u32,
+    /// Mean, in thousandths of a cycle.
+    pub mean_milli: u64,
+    /// Sample variance, in thousandths of a cycle squared.
+    pub variance_milli: u64,
+    /// 1st, 50th and 99th percentiles, the lower edge of the histogram bin.
+    /// `None` if outside the histogram range.
+    pub percentiles: [Option<u32>; 3],
+    /// Lag-1 autocorrelation of consecutive samples, in thousandths.
+    ///
+    /// Zero if there is no variance.
+    pub autocorr_milli: i32,
+}
+
+/// A rise time histogram with `BINS` bins.
+pub struct Characterize<const BINS: usize> {
+    base: u32,
+    width: u32,
+    hist: [u32; BINS],
+    below: u32,
+    above: u32,
+
+    n: u32,
+    min: u32,
+    max: u32,
+    // Sums are of values relative to the first sample, to limit their size.
+    // They saturate rather than using i128, which is slow on the M0+.
+    first: u32,
+    sum: i64,
+    sum_sq: i64,
+    // sum of products of consecutive samples
+    sum_lag: i64,
+    prev: i64,
+}
+
+impl<const BINS: usize> Characterize<BINS> {
+    /// Creates a histogram of bins `width` cycles wide, starting from `base`.
+    pub fn new(base: u32, width: u32) -> Self {
+        Self {
+            base,
+            width: width.max(1),
+            hist: [0; BINS],
+            below: 0,
+            above: 0,
+            n: 0,
+            min: u32::MAX,
+            max: 0,
+            first: 0,
+            sum: 0,
+            sum_sq: 0,
+            sum_lag: 0,
+            prev: 0,
+        }
+    }
+
+    /// Adds a rise time.
+    pub fn add(&mut self, t: u32) {
+        if t < self.base {
+            self.below += 1;
+        } else {
+            let i = ((t - self.base) / self.width) as usize;
+            match self.hist.get_mut(i) {
+                Some(h) => *h += 1,
+                None => self.above += 1,
+            }
+        }
+
+        if self.n == 0 {
+            self.first = t;
+        }
+        let d = t as i64 - self.first as i64;
+        if self.n > 0 {
+            self.sum_lag = self.sum_lag.saturating_add(self.prev.saturating_mul(d));
+        }
+        self.prev = d;
+        self.sum = self.sum.saturating_add(d);
+        self.sum_sq = self.sum_sq.saturating_add(d.saturating_mul(d));
+        self.n += 1;
+        self.min = self.min.min(t);
+        self.max = self.max.max(t);
+    }
+
+    /// Returns the histogram counts.
+    ///
+    /// Bin `i` counts rise times from `base + i * width`.
+    pub fn histogram(&self) -> &[u32; BINS] {
+        &self.hist
+    }
+
+    /// Returns counts of rise times below and above the histogram range.
+    pub fn outside(&self) -> (u32, u32) {
+        (self.below, self.above)
+    }
+
+    /// Returns the percentile `p` in thousandths, as the lower edge of a bin.
+    ///
+    /// Returns `None` if it is outside the histogram range or there are no samples.
+    pub fn percentile(&self, p: u32) -> Option<u32> {
+        if self.n == 0 {
+            return None;
+        }
+        let target = (self.n as u64 * p.min(1000) as u64).div_ceil(1000).max(1);
+        let mut cum = self.below as u64;
+        if cum >= target {
+            return None;
+        }
+        for (i, h) in self.hist.iter().enumerate() {
+            cum += *h as u64;
+            if cum >= target {
+                return Some(self.base + i as u32 * self.width);
+            }
+        }
+        None
+    }
+
+    pub fn summary(&self) -> Summary {
+        let percentiles = [10, 500, 990].map(|p| self.percentile(p));
+        if self.n == 0 {
+            return Summary { percentiles, ..Default::default() };
+        }
+
+        let n = self.n as i64;
+        let (sum, sum_sq) = (self.sum, self.sum_sq);
+        let mean_milli = (self.first as i64 * 1000)
+            .saturating_add(sum.saturating_mul(1000) / n)
+            as u64;
+
+        // sum of squared deviations
+        let sq_n = sum.saturating_mul(sum) / n;
+        let m2 = sum_sq.saturating_sub(sq_n).max(0);
+        let variance_milli =
+            if self.n > 1 { (m2.saturating_mul(1000) / (n - 1)) as u64 } else { 0 };
+
+        // Lag-1 autocovariance, m2 is the variance at the same scale
+        let last = self.prev;
+        let c1 = self
+            .sum_lag
+            .saturating_sub(sq_n)
+            .saturating_add(sum.saturating_mul(last) / n)
+            .saturating_sub(sq_n / n);
+        let autocorr_milli = if m2 > 0 {
+            (c1.saturating_mul(1000) / m2).clamp(-1000, 1000) as i32
+        } else {
+            0
+        };
+
+        Summary {
+            n: self.n,
+            min: self.min,
+            max: self.max,
+            mean_milli,
+            variance_milli,
+            percentiles,
+            autocorr_milli,
+        }
+    }
+}
+
+/// Measures `count` rise times from `noise` into `ch`.
+///
+/// SYST is used for timing, see [`RawNoise::check()`].
+#[cfg(feature = "rp2040")]
+pub fn capture<P: Pin, const BINS: usize>(
+    noise: &mut RawNoise<P>,
+    ch: &mut Characterize<BINS>,
+    count: usize,
+) -> Result<(), Error> {
+    with_systick(|syst| {
+        for _ in 0..count {
+            ch.add(noise.next_with_systick(syst)?);
+        }
+        Ok(())
+    })
+}
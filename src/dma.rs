@@ -0,0 +1,229 @@
+//! Capacitor rise times sampled by PIO and DMA.
+//!
+//! [`DmaNoise`] times the capacitor rise with a PIO state machine reading the
+//! pin on every clock cycle, rather than the CPU loop of [`RawNoise`](crate::cap::RawNoise).
+//! A DMA channel copies the pin samples from the PIO RX FIFO into a RAM buffer,
+//! the rise is found afterwards. Each cycle is resolved, without the
+//! burst positions of the CPU loop.
+//!
+//! SIO registers are only accessible to the cores, not the DMA, so the pin
+//! is read by PIO rather than copying `gpio_in`. The pin remains an SIO
+//! function, discharged by the CPU.
+
+#[cfg(not(feature = "defmt"))]
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+
+use core::any::TypeId;
+use core::future::Future;
+use core::task::{Context, Waker};
+
+use embassy_rp::dma::{Channel, Transfer};
+use embassy_rp::gpio::Pin;
+use embassy_rp::peripherals::PIO0;
+use embassy_rp::pio::program::pio_asm;
+use embassy_rp::pio::{
+    Common, Config, FifoJoin, Instance, ShiftConfig, ShiftDirection, StateMachine,
+};
+use embassy_rp::{pac, Peri};
+
+use crate::cap::{exact_low, PinSetup};
+use crate::source::NoiseSource;
+
+/// A noise source timing capacitor rise with PIO and DMA.
+///
+/// Each sample drives the pin low for `low_cycles`, then a PIO state machine
+/// samples the pin on every cycle for a window of `WORDS * 32` cycles from the
+/// pin reading low, which must exceed `low_cycles` plus the rise time.
+///
+/// The default of 64 words is a window of 2048 cycles (16µs at 125MHz), much
+/// shorter than the [`MAX_RISE_CYCLES`](crate::cap::RawNoise::MAX_RISE_CYCLES)
+/// accepted by [`RawNoise::check()`](crate::cap::RawNoise::check). Size `WORDS`
+/// for the capacitor from the rise time measured by `check()` or
+/// [`calibrate()`](crate::calibrate), a longer rise gives timeouts.
+///
+/// Output samples are the low 7 bits of the rise time in cycles, or
+/// [`TIMEOUT`](Self::TIMEOUT) if the pin didn't rise within the window.
+/// As with [`RawNoise`](crate::cap::RawNoise), samples must be conditioned
+/// before use.
+///
+/// # Examples
+///
+/// ```
+/// use embassy_rp::peripherals::{DMA_CH0, PIO0};
+/// use embassy_rp::pio::Pio;
+/// use embassy_rp::{bind_interrupts, dma, pio};
+///
+/// use caprand::dma::DmaNoise;
+///
+/// bind_interrupts!(struct Irqs {
+///     PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
+///     DMA_IRQ_0 => dma::InterruptHandler<DMA_CH0>;
+/// });
+///
+/// let p = embassy_rp::init(Default::default());
+/// let Pio { mut common, sm0, .. } = Pio::new(p.PIO0, Irqs);
+/// let dma = dma::Channel::new(p.DMA_CH0, Irqs);
+/// let mut noise = DmaNoise::<_, _, 0>::new(&mut common, sm0, dma, p.PIN_10, 1);
+/// caprand::setup_from_noise(&mut noise, &caprand::Config::default()).unwrap();
+/// ```
+pub struct DmaNoise<
+    'd,
+    P: Pin,
+    PIO: Instance,
+    const SM: usize,
+    const WORDS: usize = 64,
+> {
+    pin: Peri<'d, P>,
+    sm: StateMachine<'d, PIO, SM>,
+    config: Config<'d, PIO>,
+    dma: Channel<'d>,
+    low_cycles: u32,
+    buf: [u32; WORDS],
+    _setup: PinSetup,
+}
+
+impl<'d, P: Pin, PIO: Instance + 'static, const SM: usize, const WORDS: usize>
+    DmaNoise<'d, P, PIO, SM, WORDS>
+{
+    /// Sample value for a timeout.
+    pub const TIMEOUT: u8 = 0x80;
+
+    /// Length of the sampling window in cycles.
+    pub const WINDOW_CYCLES: u32 = WORDS as u32 * 32;
+
+    /// Loads the sampling program and configures `sm`.
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        dma: Channel<'d>,
+        pin: Peri<'d, P>,
+        low_cycles: u32,
+    ) -> Self {
+        let prg = pio_asm!(
+            // start once the pin is driven low
+            "wait 0 pin 0",
+            ".wrap_target",
+            "in pins, 1",
+            ".wrap",
+        );
+        let prg = common.load_program(&prg.program);
+
+        let mut config = Config::default();
+        config.use_program(&prg, &[]);
+        let mut pins = config.get_pins();
+        pins.in_base = pin.pin();
+        // SAFETY: the pin is only read, it is not a PIO function
+        unsafe { config.set_pins(pins) };
+        // first sample ends up in the MSB
+        config.shift_in = ShiftConfig {
+            threshold: 32,
+            direction: ShiftDirection::Left,
+            auto_fill: true,
+        };
+        config.fifo_join = FifoJoin::RxOnly;
+        sm.set_config(&config);
+
+        let setup = PinSetup::new(pin.pin());
+        Self { pin, sm, config, dma, low_cycles, buf: [0; WORDS], _setup: setup }
+    }
+
+    /// Times a single rise, in cycles from the pin reading low.
+    ///
+    /// Returns `None` if the pin didn't rise within the window,
+    /// or if samples were lost.
+    pub fn rise(&mut self) -> Option<u32> {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        self.sm.restart();
+        // jumps to the start of the program
+        self.sm.set_config(&self.config);
+        // clear any previous stall
+        let _ = self.sm.rx().stalled();
+        self.sm.set_enable(true);
+
+        let pad = pac::PADS_BANK0.gpio(self.pin.pin() as usize);
+        pad.modify(|s| s.set_pue(true));
+
+        // The state machine keeps sampling once the transfer completes,
+        // stalling when the FIFO fills. Only a stall while the transfer
+        // is in flight means the DMA didn't keep up, and samples were lost.
+        let fdebug = pio_regs::<PIO>().fdebug();
+        let stalled = || fdebug.read().rxstall() & (1 << SM) != 0;
+        let done = {
+            let mut xfer =
+                self.sm.rx().dma_pull(&mut self.dma, &mut self.buf, false);
+            critical_section::with(|_cs| exact_low(&self.pin, self.low_cycles));
+            // Each poll takes more than a cycle
+            complete(&mut xfer, 2 * Self::WINDOW_CYCLES, stalled)
+        };
+
+        self.sm.set_enable(false);
+        pad.modify(|s| s.set_pue(false));
+
+        if !done {
+            return None;
+        }
+
+        let i = self.buf.iter().position(|w| *w != 0)?;
+        Some(i as u32 * 32 + self.buf[i].leading_zeros())
+    }
+}
+
+/// Polls a transfer until it completes, at most `max` times.
+///
+/// Returns false if it didn't complete, or if `stalled` was true before it
+/// completed. The transfer is aborted on drop.
+fn complete(xfer: &mut Transfer, max: u32, stalled: impl Fn() -> bool) -> bool {
+    let mut cx = Context::from_waker(Waker::noop());
+    for _ in 0..max {
+        if stalled() {
+            return false;
+        }
+        if core::pin::Pin::new(&mut *xfer).poll(&mut cx).is_ready() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns the registers of a PIO block, the RP2040 has two.
+fn pio_regs<PIO: Instance + 'static>() -> pac::pio::Pio {
+    if TypeId::of::<PIO>() == TypeId::of::<PIO0>() {
+        pac::PIO0
+    } else {
+        pac::PIO1
+    }
+}
+
+impl<P: Pin, PIO: Instance + 'static, const SM: usize, const WORDS: usize>
+    NoiseSource for DmaNoise<'_, P, PIO, SM, WORDS>
+{
+    /// After a timeout the remainder of `buf` is filled with
+    /// [`TIMEOUT`](Self::TIMEOUT) without sampling.
+    fn fill(&mut self, buf: &mut [u8]) {
+        let mut it = buf.iter_mut();
+        for b in it.by_ref() {
+            match self.rise() {
+                Some(t) => *b = (t & 0x7f) as u8,
+                None => {
+                    *b = Self::TIMEOUT;
+                    break;
+                }
+            }
+        }
+        it.for_each(|b| *b = Self::TIMEOUT);
+    }
+
+    fn valid(&self, v: u8) -> bool {
+        !self.timeout(v)
+    }
+
+    fn timeout(&self, v: u8) -> bool {
+        v & Self::TIMEOUT != 0
+    }
+}
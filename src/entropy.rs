@@ -0,0 +1,129 @@
+//! Full entropy output, bypassing the DRBG.
+//!
+//! [`get_entropy()`] returns bits for protocols requiring full entropy, such as
+//! seeding another DRBG. Unlike [`getrandom()`](crate::getrandom), which
+//! outputs from ChaCha20, each 32 byte output block is a SHA256 hash of
+//! freshly collected samples credited with [`FULL_ENTROPY_BITS`], twice the
+//! output length (SP 800-90C full entropy from a vetted conditioning function).
+//!
+//! Every sample hashed into a block has passed health tests. A block with a
+//! failing sample is discarded and collection restarts.
+//!
+//! Output is much slower than `getrandom()`, 512 bits of credited entropy
+//! are required for each 32 bytes.
+
+#[cfg(not(feature = "defmt"))]
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+use defmt::{debug, error, info, panic, trace, warn};
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use embassy_rp::gpio::Pin;
+
+use crate::cap::RawNoise;
+use crate::pool::Collector;
+pub use crate::pool::FULL_ENTROPY_BITS;
+use crate::rng::{CapRng, Config};
+use crate::source::NoiseSource;
+use crate::Error;
+
+static ENTROPY: Mutex<RefCell<Option<FullEntropy>>> = Mutex::new(RefCell::new(None));
+
+/// Output block length in bytes.
+pub const BLOCK: usize = 32;
+
+struct FullEntropy {
+    noise: &'static mut (dyn NoiseSource + Send),
+    collector: Collector,
+}
+
+/// Sets a capacitor noise source for [`get_entropy()`].
+///
+/// The noise source is retained, so must be `'static`.
+/// Its mode is set from `config.mode`, then it is warmed up and checked as
+/// configured, see [`setup()`](crate::setup). `config.calibration` isn't used,
+/// `low_cycles` is that passed to [`RawNoise::new()`].
+///
+/// # Examples
+///
+/// ```
+/// use caprand::cap::RawNoise;
+/// use embassy_rp::peripherals::PIN_10;
+///
+/// let p = embassy_rp::init(Default::default());
+///
+/// let noise = cortex_m::singleton!(
+///     : RawNoise<'static, PIN_10> = RawNoise::new(p.PIN_10, 1)
+/// )
+/// .unwrap();
+/// caprand::entropy::setup(noise, &caprand::Config::default()).unwrap();
+///
+/// let mut key = [0u8; 32];
+/// caprand::get_entropy(&mut key).unwrap();
+/// ```
+pub fn setup<P: Pin + Send>(
+    noise: &'static mut RawNoise<'static, P>,
+    config: &Config,
+) -> Result<(), Error> {
+    noise.set_mode(config.mode);
+    CapRng::prepare(noise, config)?;
+    setup_from_noise(noise, config)
+}
+
+/// Sets another noise source for [`get_entropy()`], such as
+/// [`AdcNoise`](crate::adc::AdcNoise).
+///
+/// Only `config.estimate` is used.
+pub fn setup_from_noise(
+    noise: &'static mut (dyn NoiseSource + Send),
+    config: &Config,
+) -> Result<(), Error> {
+    let estimate = config.estimate.unwrap_or_else(|| noise.estimate());
+    let e = FullEntropy { noise, collector: Collector::new(estimate) };
+    critical_section::with(|cs| {
+        let _ = ENTROPY.borrow_ref_mut(cs).insert(e);
+    });
+    Ok(())
+}
+
+/// Fills `buf` with full entropy output.
+///
+/// Each [`BLOCK`] of output (or the final partial block) is derived from
+/// newly collected samples, see the [module documentation](self).
+/// Interrupts are only disabled while collecting each block of samples.
+///
+/// # Errors
+///
+/// Fails with [`Error::NotSetup`] if [`setup()`] hasn't been called or another
+/// call is in progress, [`Error::HealthFailed`] after repeated health test failures,
+/// or the source's [`timeout_error()`](NoiseSource::timeout_error) if it times out.
+pub fn get_entropy(buf: &mut [u8]) -> Result<(), Error> {
+    // The source is taken while collecting, so that interrupts aren't
+    // disabled throughout.
+    let mut e = critical_section::with(|cs| ENTROPY.borrow_ref_mut(cs).take())
+        .ok_or(Error::NotSetup)?;
+
+    let r = buf.chunks_mut(BLOCK).try_for_each(|b| {
+        let out = e.block()?;
+        b.copy_from_slice(&out[..b.len()]);
+        Ok(())
+    });
+
+    critical_section::with(|cs| {
+        let _ = ENTROPY.borrow_ref_mut(cs).insert(e);
+    });
+    r
+}
+
+impl FullEntropy {
+    /// Returns a hash of fresh health tested samples.
+    fn block(&mut self) -> Result<[u8; BLOCK], Error> {
+        self.collector.collect(self.noise, FULL_ENTROPY_BITS)
+    }
+}
@@ -0,0 +1,44 @@
+use core::fmt;
+
+/// Errors from caprand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The pin rises too quickly, a capacitor is not fitted.
+    NoCapacitor,
+    /// The pin didn't rise, it may be shorted to ground.
+    PinStuckLow,
+    /// The pin didn't fall, it may be shorted to the supply.
+    PinStuckHigh,
+    /// The pin rises too slowly for sampling.
+    RiseTooSlow,
+    /// Noise samples failed health tests.
+    HealthFailed,
+    /// The RNG or noise source hasn't been set up, or the noise source is in use.
+    NotSetup,
+    /// The entropy estimate credits nothing for each sample, seeding would
+    /// never complete.
+    ZeroEstimate,
+    /// An entropy source id is out of range or already registered, an
+    /// event is too long, or a configuration isn't supported.
+    InvalidInput,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::NoCapacitor => "No capacitor fitted",
+            Self::PinStuckLow => "Pin stuck low",
+            Self::PinStuckHigh => "Pin stuck high",
+            Self::RiseTooSlow => "Pin rise too slow",
+            Self::HealthFailed => "Health tests failed",
+            Self::NotSetup => "Not set up",
+            Self::ZeroEstimate => "Zero entropy estimate",
+            Self::InvalidInput => "Invalid input",
+        };
+        f.write_str(s)
+    }
+}
+
+impl core::error::Error for Error {}
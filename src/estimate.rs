@@ -0,0 +1,92 @@
+//! Min-entropy estimation for noise samples.
+//!
+//! Estimates are in millibits per sample. Integer arithmetic is used
+//! throughout, the RP2040 has no floating point unit.
+//!
+//! Ref NIST SP 800-90B 6.3
+
+/// Returns `log2(x)` in millibits.
+///
+/// `x` must be non-zero.
+pub(crate) fn log2_milli(x: u64) -> u32 {
+    debug_assert!(x != 0);
+    let int = x.ilog2();
+    // normalise to a 1.31 fixed point value in [1, 2)
+    let mut y = if int > 31 { x >> (int - 31) } else { x << (31 - int) };
+    // successive squaring yields one fractional bit per iteration
+    let mut frac = 0u32;
+    for _ in 0..10 {
+        y = (y * y) >> 31;
+        frac <<= 1;
+        if y >= 1 << 32 {
+            y >>= 1;
+            frac |= 1;
+        }
+    }
+    int * 1000 + frac * 1000 / 1024
+}
+
+/// Most Common Value estimate.
+///
+/// `max` is the count of the most common value from `n` samples.
+/// Returns the min-entropy estimate in millibits per sample.
+///
+/// Ref NIST SP 800-90B 6.3.1
+pub fn mcv(max: u32, n: u32) -> u32 {
+    if n < 2 || max == 0 {
+        return 0;
+    }
+    const ONE: u64 = 1 << 16;
+    let n = n as u64;
+    // probability as 16.16 fixed point
+    let p = max as u64 * ONE / n;
+    // upper bound of the 99% confidence interval
+    let var = p * (ONE - p.min(ONE)) / (n - 1);
+    let pu = p + 2576 * var.isqrt() / 1000;
+    let pu = pu.clamp(1, ONE);
+    // -log2(pu)
+    16_000 - log2_milli(pu)
+}
+
+/// An online Most Common Value estimator.
+///
+/// Samples are counted over a window, with an estimate produced
+/// at the end of each window. Samples must be 6-bit values, as
+/// produced by [`RawNoise`](crate::cap::RawNoise).
+pub struct OnlineMcv {
+    counts: [u16; 64],
+    n: u16,
+    window: u16,
+    estimate: Option<u32>,
+}
+
+impl OnlineMcv {
+    /// Creates an estimator with a given window size.
+    pub fn new(window: u16) -> Self {
+        Self { counts: [0; 64], n: 0, window, estimate: None }
+    }
+
+    /// Adds a sample, returning the new estimate if a window completed.
+    pub fn feed(&mut self, val: u8) -> Option<u32> {
+        let c = &mut self.counts[(val & 0x3f) as usize];
+        *c = c.saturating_add(1);
+        self.n += 1;
+
+        if self.n < self.window {
+            return None;
+        }
+        let max = self.counts.iter().max().copied().unwrap_or(0);
+        self.estimate = Some(mcv(max as u32, self.n as u32));
+        self.counts = [0; 64];
+        self.n = 0;
+        self.estimate
+    }
+
+    /// Returns the estimate from the most recent complete window,
+    /// in millibits per sample.
+    ///
+    /// Returns `None` until a window has completed.
+    pub fn estimate(&self) -> Option<u32> {
+        self.estimate
+    }
+}
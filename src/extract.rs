@@ -0,0 +1,438 @@
+//! Debiasing extractors for raw noise bits.
+//!
+//! These produce unbiased bits from a source of independent biased bits,
+//! for research or for feeding another entropy pool. The [`Extract`] trait
+//! adds adaptors to any iterator of bits, and [`lsb_bits()`] takes bits from
+//! [`RawNoise`](crate::cap::RawNoise) samples.
+//!
+//! - [`VonNeumann`] outputs the first bit of each unequal pair, yielding up to
+//!   1/4 bit per input bit.
+//! - [`Peres`] iterates Von Neumann over the discarded information, approaching
+//!   the entropy of the source.
+//! - [`Elias`] ranks blocks of bits among those of equal weight, also approaching
+//!   the entropy for large blocks.
+//!
+//! The extractors assume input bits are independent and identically distributed.
+//! Capacitor samples are correlated, so output bits are not guaranteed unbiased
+//! and must not be used directly as keys, see [`CapRng`](crate::CapRng) instead.
+//! Sampling both capacitor edges with [`Mode::Both`](crate::cap::Mode::Both)
+//! interleaves two distributions, so a single edge should be used.
+//!
+//! # Examples
+//!
+//! A synthetic source with bits set 70% of the time, which has 0.88 bits of
+//! Shannon entropy per bit.
+//! ```
+//! use caprand::extract::Extract;
+//!
+//! // xorshift32
+//! let mut x = 0x1234_5678u32;
+//! let mut biased = core::iter::from_fn(move || {
+//!     x ^= x << 13;
+//!     x ^= x >> 17;
+//!     x ^= x << 5;
+//!     Some(x % 1000 < 700)
+//! });
+//!
+//! let n = 100_000;
+//! let ones = biased.by_ref().von_neumann().take(n).filter(|b| *b).count();
+//! // within 1%, over 6 standard deviations
+//! assert!(ones.abs_diff(n / 2) < n / 100);
+//!
+//! // Peres and Elias output more than Von Neumann's 0.21 bits per input bit
+//! let input = 64 * 2000;
+//! assert!(biased.by_ref().take(input).peres(8).count() > input * 2 / 3);
+//! assert!(biased.by_ref().take(input).elias::<64>().count() > input * 3 / 4);
+//! ```
+
+#[cfg(feature = "rp2040")]
+use crate::cap::{lsb, Sample};
+
+/// Returns one bit from each valid sample, the parity of [`lsb()`](crate::cap::lsb).
+///
+/// Invalid samples are skipped, and iteration ends on a timeout.
+#[cfg(feature = "rp2040")]
+pub fn lsb_bits<I: Iterator<Item = Sample>>(
+    samples: I,
+) -> impl Iterator<Item = bool> {
+    samples
+        .map_while(|s| match s {
+            Sample::Value(v, valid) => Some((v, valid)),
+            Sample::Timeout => None,
+        })
+        .filter(|(_, valid)| *valid)
+        .map(|(v, _)| lsb(v) & 1 == 1)
+}
+
+/// Extractor adaptors for iterators of bits.
+pub trait Extract: Iterator<Item = bool> + Sized {
+    /// Returns a [`VonNeumann`] extractor.
+    fn von_neumann(self) -> VonNeumann<Self> {
+        VonNeumann { inner: self }
+    }
+
+    /// Returns a [`Peres`] extractor with `depth` iterations.
+    ///
+    /// A `depth` of 1 is equivalent to Von Neumann.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is 0, which would never output.
+    fn peres(self, depth: u32) -> Peres<Self> {
+        Peres::new(self, depth)
+    }
+
+    /// Returns an [`Elias`] extractor with blocks of `N` bits, from 2 to 64.
+    fn elias<const N: usize>(self) -> Elias<Self, N> {
+        Elias::new(self)
+    }
+
+    /// Packs bits into bytes, least significant bit first.
+    fn bytes(self) -> Bytes<Self> {
+        Bytes { inner: self }
+    }
+}
+
+impl<I: Iterator<Item = bool>> Extract for I {}
+
+/// Von Neumann extractor.
+///
+/// Input bits are taken in pairs, `01` outputs 0 and `10` outputs 1.
+/// Equal pairs are discarded.
+///
+/// Ref J. von Neumann, "Various techniques used in connection with random digits", 1951.
+pub struct VonNeumann<I> {
+    inner: I,
+}
+
+impl<I: Iterator<Item = bool>> Iterator for VonNeumann<I> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        loop {
+            let a = self.inner.next()?;
+            let b = self.inner.next()?;
+            if a != b {
+                return Some(a);
+            }
+        }
+    }
+}
+
+const PERES_BLOCK: usize = 256;
+
+/// Iterated Von Neumann extractor.
+///
+/// Each block of [`BLOCK`](Self::BLOCK) bits outputs the Von Neumann bits,
+/// followed by the extractor applied recursively to the XOR of each pair,
+/// and to the first bit of each equal pair. Recursion is limited to `depth`.
+/// A trailing partial block is discarded.
+///
+/// Ref Y. Peres, "Iterating von Neumann's procedure for extracting random bits", 1992.
+///
+/// See the [module documentation](self) for an example.
+pub struct Peres<I> {
+    inner: I,
+    depth: u32,
+    out: [bool; PERES_BLOCK],
+    len: usize,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = bool>> Peres<I> {
+    /// Input bits processed at a time.
+    pub const BLOCK: usize = PERES_BLOCK;
+
+    fn new(inner: I, depth: u32) -> Self {
+        assert!(depth >= 1, "Peres depth must be at least 1");
+        Self { inner, depth, out: [false; PERES_BLOCK], len: 0, pos: 0 }
+    }
+
+    /// Extracts the next block, returning `false` at the end of input.
+    fn refill(&mut self) -> bool {
+        let mut x = [false; PERES_BLOCK];
+        for b in x.iter_mut() {
+            match self.inner.next() {
+                Some(v) => *b = v,
+                None => return false,
+            }
+        }
+
+        // each level of recursion uses at most its input length
+        let mut scratch = [false; 2 * PERES_BLOCK];
+        self.len = 0;
+        self.pos = 0;
+        peres(&x, self.depth, &mut scratch, &mut self.out, &mut self.len);
+        true
+    }
+}
+
+/// Appends the Peres extraction of `x` to `out`.
+///
+/// The output is no longer than `x`.
+fn peres(
+    x: &[bool],
+    depth: u32,
+    scratch: &mut [bool],
+    out: &mut [bool],
+    len: &mut usize,
+) {
+    if depth == 0 || x.len() < 2 {
+        return;
+    }
+
+    let (u, rest) = scratch.split_at_mut(x.len() / 2);
+    let mut equal = 0;
+    let (pairs, _) = x.as_chunks::<2>();
+    for (&[a, b], u) in pairs.iter().zip(u.iter_mut()) {
+        if a != b {
+            out[*len] = a;
+            *len += 1;
+        } else {
+            rest[equal] = a;
+            equal += 1;
+        }
+        *u = a ^ b;
+    }
+
+    let (v, rest) = rest.split_at_mut(equal);
+    peres(u, depth - 1, rest, out, len);
+    peres(v, depth - 1, rest, out, len);
+}
+
+impl<I: Iterator<Item = bool>> Iterator for Peres<I> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        while self.pos == self.len {
+            if !self.refill() {
+                return None;
+            }
+        }
+        let b = self.out[self.pos];
+        self.pos += 1;
+        Some(b)
+    }
+}
+
+/// Elias extractor.
+///
+/// Each block of `N` bits (2 to 64) with `k` bits set is ranked
+/// among the `C(N, k)` blocks of the same weight. That count is split
+/// into segments of decreasing powers of two, and the offset within the
+/// segment containing the rank is output, most significant bit first.
+/// A trailing partial block is discarded.
+///
+/// Ref P. Elias, "The efficient construction of an unbiased random sequence", 1972.
+///
+/// See the [module documentation](self) for an example.
+pub struct Elias<I, const N: usize> {
+    inner: I,
+    // output bits, most significant first
+    out: u64,
+    len: u32,
+}
+
+impl<I: Iterator<Item = bool>, const N: usize> Elias<I, N> {
+    fn new(inner: I) -> Self {
+        // a single bit block has one block per weight, and never outputs
+        const { assert!(N >= 2 && N <= 64) };
+        Self { inner, out: 0, len: 0 }
+    }
+
+    /// Extracts the next block, returning `false` at the end of input.
+    fn refill(&mut self) -> bool {
+        let mut x = 0u64;
+        for _ in 0..N {
+            match self.inner.next() {
+                Some(b) => x = x << 1 | b as u64,
+                None => return false,
+            }
+        }
+
+        // lexicographic rank among blocks with the same weight
+        let mut k = x.count_ones() as usize;
+        let mut total = binomial(N, k);
+        let mut rank = 0;
+        for i in (0..N).rev() {
+            if x & 1 << i != 0 {
+                // blocks with a 0 here and the remaining bits set after
+                rank += binomial(i, k);
+                k -= 1;
+            }
+        }
+
+        // segments of size 2^m for each bit m set in total, largest first
+        self.len = 0;
+        while total > 0 {
+            let m = total.ilog2();
+            let seg = 1u64 << m;
+            if rank < seg {
+                self.out = rank;
+                self.len = m;
+                break;
+            }
+            rank -= seg;
+            total -= seg;
+        }
+        true
+    }
+}
+
+/// Returns the binomial coefficient `C(n, k)`, for `n` at most 64.
+fn binomial(n: usize, k: usize) -> u64 {
+    if k > n {
+        return 0;
+    }
+    let k = k.min(n - k);
+    let mut c = 1u128;
+    for i in 0..k {
+        // exact, c * (n - i) is divisible by i + 1
+        c = c * (n - i) as u128 / (i + 1) as u128;
+    }
+    c as u64
+}
+
+impl<I: Iterator<Item = bool>, const N: usize> Iterator for Elias<I, N> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        while self.len == 0 {
+            if !self.refill() {
+                return None;
+            }
+        }
+        self.len -= 1;
+        Some(self.out >> self.len & 1 == 1)
+    }
+}
+
+/// Packs bits into bytes, see [`Extract::bytes()`].
+///
+/// A trailing partial byte is discarded.
+pub struct Bytes<I> {
+    inner: I,
+}
+
+impl<I: Iterator<Item = bool>> Iterator for Bytes<I> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let mut v = 0u8;
+        for i in 0..8 {
+            v |= (self.inner.next()? as u8) << i;
+        }
+        Some(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Independent bits set `permille` / 1000 of the time.
+    fn biased(permille: u32) -> impl Iterator<Item = bool> {
+        // xorshift32
+        let mut x = 0x1234_5678u32;
+        core::iter::from_fn(move || {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            Some(x % 1000 < permille)
+        })
+    }
+
+    /// Returns (output bits, ones).
+    fn count(it: impl Iterator<Item = bool>) -> (usize, usize) {
+        it.fold((0, 0), |(n, ones), b| (n + 1, ones + b as usize))
+    }
+
+    #[test]
+    fn von_neumann_pairs() {
+        let x = [false, true, true, false, true, true, false, false, true];
+        assert!(x.into_iter().von_neumann().eq([false, true]));
+    }
+
+    #[test]
+    fn elias_exact() {
+        // Blocks of the same weight are equally likely for any bias, so
+        // outputs of each length from each weight must be uniform.
+        const N: usize = 8;
+        // [weight][length][value]
+        let mut counts = [[[0u32; 1 << N]; N + 1]; N + 1];
+        for x in 0..1u32 << N {
+            let bits = (0..N).map(|i| x >> (N - 1 - i) & 1 == 1);
+            let mut len = 0;
+            let mut v = 0;
+            for b in bits.elias::<N>() {
+                v = v << 1 | b as usize;
+                len += 1;
+            }
+            counts[x.count_ones() as usize][len][v] += 1;
+        }
+
+        let mut total = 0;
+        for (k, c) in counts.iter().enumerate() {
+            for (m, c) in c.iter().enumerate() {
+                let (used, unused) = c.split_at(1 << m);
+                assert!(unused.iter().all(|c| *c == 0));
+                assert!(used.iter().all(|c| *c == used[0]), "k {k} m {m} {used:?}");
+                total += used.iter().sum::<u32>();
+            }
+            // lengths of output are the bits set in C(N, k)
+            let lengths =
+                (0..=N).filter(|m| c[*m][0] > 0).fold(0, |a, m| a | 1 << m);
+            assert_eq!(lengths, binomial(N, k) as usize);
+        }
+        assert_eq!(total, 1 << N);
+    }
+
+    #[test]
+    fn elias_rate() {
+        let input = 64 * 2000;
+        let (n, ones) = count(biased(700).take(input).elias::<64>());
+        assert!(n > input * 3 / 4);
+        assert!(ones.abs_diff(n / 2) < n / 100);
+    }
+
+    #[test]
+    fn peres_depth_one() {
+        let input = PERES_BLOCK * 10;
+        let a = biased(700).take(input).peres(1);
+        let b = biased(700).take(input).von_neumann();
+        assert!(a.eq(b));
+    }
+
+    #[test]
+    fn peres_rate() {
+        let input = PERES_BLOCK * 400;
+        let (vn, vn_ones) = count(biased(700).take(input).von_neumann());
+        let (n, ones) = count(biased(700).take(input).peres(8));
+        // Von Neumann yields p(1-p) = 0.21 bits per input bit,
+        // the source has 0.88 bits of Shannon entropy
+        assert!(vn.abs_diff(input * 21 / 100) < input / 100);
+        assert!(n > input * 2 / 3);
+        assert!(n < input * 88 / 100);
+        assert!(vn_ones.abs_diff(vn / 2) < vn / 50);
+        assert!(ones.abs_diff(n / 2) < n / 100);
+    }
+
+    #[test]
+    #[should_panic]
+    fn peres_depth_zero() {
+        let _ = biased(500).peres(0);
+    }
+
+    #[test]
+    fn peres_partial_block() {
+        assert_eq!(biased(500).take(PERES_BLOCK - 1).peres(4).count(), 0);
+    }
+
+    #[test]
+    fn bytes_lsb_first() {
+        let x = [true, false, false, false, false, false, false, true, true];
+        let mut b = x.into_iter().bytes();
+        assert_eq!(b.next(), Some(0x81));
+        assert_eq!(b.next(), None);
+    }
+}
@@ -0,0 +1,243 @@
+//! A Fortuna style generator with multiple entropy pools.
+//!
+//! [`FortunaCapRng`] continues sampling the capacitor after seeding, spreading
+//! samples across 32 pools. Reseed `k` uses pool `i` only when `2^i` divides `k`,
+//! so higher pools accumulate entropy over longer periods. An attacker who
+//! learns the generator state can't keep it compromised once a reseed uses a pool
+//! with sufficient entropy. This doesn't rely on an accurate entropy estimate.
+//!
+//! Ref Ferguson, Schneier and Kohno, "Cryptography Engineering", chapter 9.
+
+#[cfg(not(feature = "defmt"))]
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+use defmt::{debug, error, info, panic, trace, warn};
+
+use core::convert::Infallible;
+
+use rand::Rng;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+use embassy_rp::{gpio::Pin, Peri};
+
+use crate::cap::RawNoise;
+use crate::health::TotalHealth;
+use crate::rng::{CapRng, Config};
+use crate::source::{NoiseSource, CAP_SOURCE, MAX_SOURCES};
+use crate::Error;
+
+/// Number of pools.
+pub const POOLS: usize = 32;
+
+/// Maximum length of a single event.
+pub const MAX_EVENT: usize = 32;
+
+/// Accumulates events from entropy sources into pools.
+pub struct Accumulator {
+    pools: [Sha256; POOLS],
+    // next pool for each source
+    next: [u8; MAX_SOURCES],
+    // bytes added to pool 0 since the last reseed
+    pool0_len: usize,
+    reseeds: u32,
+}
+
+impl Accumulator {
+    pub fn new() -> Self {
+        Self {
+            pools: core::array::from_fn(|_| Sha256::new()),
+            next: [0; MAX_SOURCES],
+            pool0_len: 0,
+            reseeds: 0,
+        }
+    }
+
+    /// Adds an event from a source.
+    ///
+    /// Each source's events are spread round-robin across the pools.
+    /// Fails with [`Error::InvalidInput`] if `source` is out of range or `data`
+    /// is longer than [`MAX_EVENT`].
+    pub fn add_event(&mut self, source: u8, data: &[u8]) -> Result<(), Error> {
+        let next = self.next.get_mut(source as usize).ok_or(Error::InvalidInput)?;
+        if data.len() > MAX_EVENT {
+            return Err(Error::InvalidInput);
+        }
+
+        let i = *next as usize;
+        self.pools[i].update([source, data.len() as u8]);
+        self.pools[i].update(data);
+        if i == 0 {
+            self.pool0_len += data.len();
+        }
+        *next = ((i + 1) % POOLS) as u8;
+        Ok(())
+    }
+
+    /// Returns the number of bytes added to pool 0 since the last reseed.
+    pub fn pool0_len(&self) -> usize {
+        self.pool0_len
+    }
+
+    /// Returns the number of reseeds so far.
+    pub fn reseeds(&self) -> u32 {
+        self.reseeds
+    }
+
+    /// Returns seed material for the next reseed.
+    ///
+    /// Pools used are emptied.
+    pub fn reseed(&mut self) -> [u8; 32] {
+        self.reseeds = self.reseeds.wrapping_add(1);
+        // pool i is used when 2^i divides the reseed count
+        let used = (self.reseeds.trailing_zeros() as usize + 1).min(POOLS);
+
+        let mut h = Sha256::new();
+        for p in self.pools[..used].iter_mut() {
+            h.update(p.finalize_reset());
+        }
+        self.pool0_len = 0;
+        h.finalize().into()
+    }
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A cryptographic PRNG with Fortuna style reseeding from the capacitor noise source.
+///
+/// This is an alternative to [`CapRng`] for long running devices. It keeps
+/// the pin, and [`collect()`](Self::collect) should be called periodically
+/// to sample the capacitor and reseed.
+///
+/// The generator is rekeyed after every request, so a later compromise of state
+/// can't recover previous output.
+pub struct FortunaCapRng<'a, P: Pin> {
+    noise: RawNoise<'a, P>,
+    // indexed by cap::fall()
+    health: [TotalHealth; 2],
+    acc: Accumulator,
+    key: [u8; 32],
+    rng: ChaCha20Rng,
+}
+
+impl<'a, P: Pin> FortunaCapRng<'a, P> {
+    /// Capacitor sample bytes required in pool 0 before a reseed.
+    pub const MIN_POOL_SAMPLES: usize = CapRng::SEED_SAMPLES / 8;
+
+    /// Seeds from the capacitor in the same way as [`CapRng`].
+    pub fn new(pin: Peri<'a, P>, config: &Config) -> Result<Self, Error> {
+        let mut noise = RawNoise::new(pin, config.low_cycles());
+        noise.set_mode(config.mode);
+        CapRng::prepare(&mut noise, config)?;
+        let key = CapRng::seed(&mut noise, config)?;
+        Ok(Self {
+            noise,
+            health: core::array::from_fn(|_| TotalHealth::new()),
+            acc: Accumulator::new(),
+            key,
+            rng: ChaCha20Rng::from_seed(key),
+        })
+    }
+
+    /// Collects a block of capacitor samples into the pools.
+    ///
+    /// Reseeds once pool 0 has [`MIN_POOL_SAMPLES`](Self::MIN_POOL_SAMPLES),
+    /// returning `true`. A block failing health tests or timing out is
+    /// discarded, returning [`Error::HealthFailed`] or the timeout error.
+    pub fn collect(&mut self) -> Result<bool, Error> {
+        let mut block = [0u8; CapRng::BLOCK];
+        self.noise.fill(&mut block);
+
+        let mut healthy = Ok(());
+        for v in block.iter().copied() {
+            let health = &mut self.health[crate::cap::fall(v) as usize];
+            if crate::cap::timeout(v) {
+                health.timeout();
+                healthy = Err(self.noise.timeout_error(v));
+            } else if crate::cap::valid(v) {
+                if health.test(v).is_err() {
+                    healthy = healthy.and(Err(Error::HealthFailed));
+                }
+            } else {
+                health.skip();
+            }
+        }
+        self.health.iter_mut().for_each(|h| h.publish());
+        healthy?;
+
+        for ev in block.chunks(MAX_EVENT) {
+            // OK unwrap, source and length are valid
+            self.acc.add_event(CAP_SOURCE, ev).unwrap();
+        }
+
+        if self.acc.pool0_len() >= Self::MIN_POOL_SAMPLES {
+            self.reseed();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Adds an event from another entropy source.
+    ///
+    /// `source` must be non-zero and less than [`MAX_SOURCES`],
+    /// `data` is at most [`MAX_EVENT`] bytes, otherwise fails with
+    /// [`Error::InvalidInput`].
+    pub fn add_event(&mut self, source: u8, data: &[u8]) -> Result<(), Error> {
+        if source == CAP_SOURCE {
+            return Err(Error::InvalidInput);
+        }
+        self.acc.add_event(source, data)
+    }
+
+    /// Returns the number of reseeds so far.
+    pub fn reseeds(&self) -> u32 {
+        self.acc.reseeds()
+    }
+
+    fn reseed(&mut self) {
+        let s = self.acc.reseed();
+        let mut h = Sha256::new();
+        h.update(self.key);
+        h.update(s);
+        self.key = h.finalize().into();
+        self.rng = ChaCha20Rng::from_seed(self.key);
+        debug!("Reseed {}", self.acc.reseeds());
+    }
+
+    fn rekey(&mut self) {
+        self.rng.fill_bytes(&mut self.key);
+        self.rng = ChaCha20Rng::from_seed(self.key);
+    }
+}
+
+impl<P: Pin> rand::TryCryptoRng for FortunaCapRng<'_, P> {}
+
+impl<P: Pin> rand::TryRng for FortunaCapRng<'_, P> {
+    type Error = Infallible;
+    fn try_next_u32(&mut self) -> Result<u32, Self::Error> {
+        let mut b = [0u8; 4];
+        self.try_fill_bytes(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn try_next_u64(&mut self) -> Result<u64, Self::Error> {
+        let mut b = [0u8; 8];
+        self.try_fill_bytes(&mut b)?;
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Self::Error> {
+        self.rng.fill_bytes(dest);
+        self.rekey();
+        Ok(())
+    }
+}
@@ -6,6 +6,137 @@ use log::{debug, error, info, trace, warn};
 #[allow(unused_imports)]
 use defmt::{debug, error, info, panic, trace, warn};
 
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use crate::Error;
+
+/// Health test statistics.
+///
+/// Statistics from all health tests are accumulated globally,
+/// see [`health_stats()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HealthStats {
+    /// Valid samples tested.
+    pub tested: u32,
+    /// Invalid samples, these are not tested.
+    pub invalid: u32,
+    /// Samples where the noise source failed, such as a capacitor
+    /// pin that didn't rise.
+    pub timeouts: u32,
+    /// Longest run of a repeated value.
+    pub max_repetition: u32,
+    /// Most matches in an adaptive proportion window.
+    pub max_matches: u32,
+    pub repetition_failures: u32,
+    pub adaptive_failures: u32,
+    /// The most recent value that failed a test.
+    pub last_failure: Option<u8>,
+    /// Completed [`LsbMonitor`] windows.
+    pub lsb_windows: u32,
+    /// [`LsbMonitor`] windows exceeding the cutoff.
+    pub lsb_anomalies: u32,
+    /// Largest [`LsbMonitor`] chi-square statistic, in thousandths.
+    pub max_chi2: u32,
+    /// `clk_sys` frequency of the most recent noise source, in Hz.
+    pub clk_sys: u32,
+    /// The most recent warm-up.
+    pub warmup: Option<Warmup>,
+}
+
+impl HealthStats {
+    /// Returns the total number of samples seen.
+    pub fn samples(&self) -> u32 {
+        self.tested.wrapping_add(self.invalid).wrapping_add(self.timeouts)
+    }
+
+    /// Returns the total number of test failures, including timeouts.
+    pub fn failures(&self) -> u32 {
+        self.repetition_failures
+            .wrapping_add(self.adaptive_failures)
+            .wrapping_add(self.timeouts)
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.tested = self.tested.wrapping_add(other.tested);
+        self.invalid = self.invalid.wrapping_add(other.invalid);
+        self.timeouts = self.timeouts.wrapping_add(other.timeouts);
+        self.max_repetition = self.max_repetition.max(other.max_repetition);
+        self.max_matches = self.max_matches.max(other.max_matches);
+        self.repetition_failures =
+            self.repetition_failures.wrapping_add(other.repetition_failures);
+        self.adaptive_failures =
+            self.adaptive_failures.wrapping_add(other.adaptive_failures);
+        self.last_failure = other.last_failure.or(self.last_failure);
+        self.lsb_windows = self.lsb_windows.wrapping_add(other.lsb_windows);
+        self.lsb_anomalies = self.lsb_anomalies.wrapping_add(other.lsb_anomalies);
+        self.max_chi2 = self.max_chi2.max(other.max_chi2);
+        if other.clk_sys != 0 {
+            self.clk_sys = other.clk_sys;
+        }
+        self.warmup = other.warmup.or(self.warmup);
+    }
+}
+
+/// Rise times of warm-up samples, discarded before use.
+///
+/// The first samples after setup can differ from later samples, due to
+/// flash cache loads and the initial capacitor charge.
+/// Deviations much larger than `spread` indicate a cold start effect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Warmup {
+    /// Samples discarded.
+    pub discarded: u32,
+    /// Median rise time of samples following warm-up, in cycles.
+    pub median: u32,
+    /// Range of rise times following warm-up, in cycles.
+    pub spread: u32,
+    /// Difference of the first rise time from `median`, in cycles.
+    pub first_deviation: u32,
+    /// Largest difference of a warm-up rise time from `median`, in cycles.
+    pub max_deviation: u32,
+}
+
+static STATS: Mutex<RefCell<HealthStats>> = Mutex::new(RefCell::new(HealthStats {
+    tested: 0,
+    invalid: 0,
+    timeouts: 0,
+    max_repetition: 0,
+    max_matches: 0,
+    repetition_failures: 0,
+    adaptive_failures: 0,
+    last_failure: None,
+    lsb_windows: 0,
+    lsb_anomalies: 0,
+    max_chi2: 0,
+    clk_sys: 0,
+    warmup: None,
+}));
+
+/// Records the `clk_sys` frequency of a noise source.
+#[cfg(feature = "rp2040")]
+pub(crate) fn record_clk_sys(clk_sys: u32) {
+    critical_section::with(|cs| STATS.borrow_ref_mut(cs).clk_sys = clk_sys)
+}
+
+/// Records the result of a noise source warm-up.
+#[cfg(feature = "rp2040")]
+pub(crate) fn record_warmup(warmup: Warmup) {
+    critical_section::with(|cs| STATS.borrow_ref_mut(cs).warmup = Some(warmup))
+}
+
+/// Returns health test statistics accumulated since boot.
+///
+/// Statistics are updated after each block of samples.
+/// Maximum run and match counts approaching the test cutoffs
+/// can indicate a degrading noise source.
+pub fn health_stats() -> HealthStats {
+    critical_section::with(|cs| *STATS.borrow_ref(cs))
+}
+
 struct Repetition {
     prev: u8,
     count: usize,
@@ -36,15 +167,19 @@ impl Repetition {
 pub struct RepetitionTest {
     r: Repetition,
     cutoff: usize,
+    // longest run observed
+    max: usize,
 }
 
 impl RepetitionTest {
     pub fn new(cutoff: usize) -> Self {
-        Self { r: Repetition::new(), cutoff }
+        Self { r: Repetition::new(), cutoff, max: 0 }
     }
 
     pub fn test(&mut self, val: u8) -> Result<(), ()> {
-        if self.r.feed(val) < self.cutoff {
+        let count = self.r.feed(val);
+        self.max = self.max.max(count);
+        if count < self.cutoff {
             Ok(())
         } else {
             warn!("Repetition test failed for value {}", val);
@@ -68,11 +203,14 @@ pub struct AdaptiveProportionTest {
     window: usize,
     // C cutoff. failure occurs if matches >= cutoff
     cutoff: usize,
+
+    // most matches observed
+    max: usize,
 }
 
 impl AdaptiveProportionTest {
     pub fn new(window: usize, cutoff: usize) -> Self {
-        Self { val: 0, matches: 0, i: 0, window, cutoff }
+        Self { val: 0, matches: 0, i: 0, window, cutoff, max: 0 }
     }
 
     pub fn test(&mut self, val: u8) -> Result<(), ()> {
@@ -84,7 +222,8 @@ impl AdaptiveProportionTest {
             Ok(())
         } else {
             if self.val == val {
-                self.matches += 1
+                self.matches += 1;
+                self.max = self.max.max(self.matches);
             }
             let result = self.matches < self.cutoff;
             self.i += 1;
@@ -107,6 +246,8 @@ impl AdaptiveProportionTest {
 pub struct TotalHealth {
     adaptive: AdaptiveProportionTest,
     repetition: RepetitionTest,
+    // since the last publish()
+    stats: HealthStats,
 }
 
 impl TotalHealth {
@@ -115,12 +256,140 @@ impl TotalHealth {
             adaptive: AdaptiveProportionTest::new(512, 410),
             // 201 for H = 0.1, alpha = 2**-20
             repetition: RepetitionTest::new(201),
+            stats: HealthStats::default(),
         }
     }
 
+    /// Tests a valid sample.
     pub fn test(&mut self, val: u8) -> Result<(), ()> {
-        self.adaptive.test(val)?;
-        self.repetition.test(val)?;
-        Ok(())
+        let s = &mut self.stats;
+        s.tested = s.tested.wrapping_add(1);
+
+        let a = self.adaptive.test(val);
+        if a.is_err() {
+            s.adaptive_failures = s.adaptive_failures.wrapping_add(1);
+            s.last_failure = Some(self.adaptive.val);
+        }
+        let r = self.repetition.test(val);
+        if r.is_err() {
+            s.repetition_failures = s.repetition_failures.wrapping_add(1);
+            s.last_failure = Some(val);
+        }
+        a.and(r)
+    }
+
+    /// Counts an invalid sample, which is not tested.
+    pub fn skip(&mut self) {
+        self.stats.invalid = self.stats.invalid.wrapping_add(1);
+    }
+
+    /// Counts a sample where the noise source failed.
+    pub fn timeout(&mut self) {
+        self.stats.timeouts = self.stats.timeouts.wrapping_add(1);
+    }
+
+    /// Adds statistics since the last call to the global [`health_stats()`].
+    pub fn publish(&mut self) {
+        let s = &mut self.stats;
+        s.max_repetition = core::mem::take(&mut self.repetition.max) as u32;
+        s.max_matches = core::mem::take(&mut self.adaptive.max) as u32;
+        critical_section::with(|cs| STATS.borrow_ref_mut(cs).merge(s));
+        *s = HealthStats::default();
+    }
+}
+
+/// Monitors the distribution of the first bit set in capacitor samples.
+///
+/// The `time_rise()` and `time_fall()` loops take 13 cycles, so the expected
+/// position of the lowest set bit (`cap::lsb()`) is 0 with probability 8/13,
+/// and 1 to 5 with probability 1/13 each. A chi-square statistic against that
+/// distribution is computed for each window of samples. A missing capacitor,
+/// a shorted pin or a wrong clock will distort the distribution.
+///
+/// Results are added to [`health_stats()`]. Anomalies don't fail seeding,
+/// since real capacitors may deviate somewhat from the ideal distribution.
+pub struct LsbMonitor {
+    // lowest bit 0 to 5, or 6 for timeouts
+    counts: [u16; 7],
+    n: u16,
+    cutoff: u32,
+    last: Option<u32>,
+}
+
+impl LsbMonitor {
+    /// Samples in each window, a multiple of 13.
+    pub const WINDOW: u16 = 4160;
+
+    /// Default cutoff in thousandths, for 5 degrees of freedom at p = 2**-20.
+    pub const CUTOFF: u32 = 35_991;
+
+    // Expected proportion of each position, in thirteenths
+    const EXPECTED: [u32; 6] = [8, 1, 1, 1, 1, 1];
+
+    /// Creates a monitor with `cutoff` for the chi-square statistic, in thousandths.
+    pub fn new(cutoff: u32) -> Self {
+        Self { counts: [0; 7], n: 0, cutoff, last: None }
+    }
+
+    /// Adds a sample.
+    ///
+    /// Returns [`Error::HealthFailed`] when a window completes exceeding the cutoff.
+    pub fn feed(&mut self, val: u8) -> Result<(), Error> {
+        // timeouts have the final bit clear
+        let pos = if val & 0x20 == 0 { 6 } else { val.trailing_zeros() as usize };
+        self.counts[pos] += 1;
+        self.n += 1;
+        if self.n < Self::WINDOW {
+            return Ok(());
+        }
+
+        let chi2 = self.chi2();
+        self.last = Some(chi2);
+        self.counts = [0; 7];
+        self.n = 0;
+
+        let anomaly = chi2 >= self.cutoff;
+        if anomaly {
+            warn!("Sample distribution anomaly, chi-square {}", chi2 / 1000);
+        }
+        critical_section::with(|cs| {
+            let mut s = STATS.borrow_ref_mut(cs);
+            s.lsb_windows = s.lsb_windows.wrapping_add(1);
+            s.lsb_anomalies = s.lsb_anomalies.wrapping_add(anomaly as u32);
+            s.max_chi2 = s.max_chi2.max(chi2);
+        });
+        if anomaly {
+            Err(Error::HealthFailed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the chi-square statistic of the last completed window,
+    /// in thousandths.
+    pub fn last(&self) -> Option<u32> {
+        self.last
+    }
+
+    fn chi2(&self) -> u32 {
+        // Timeouts are always anomalous
+        if self.counts[6] > 0 {
+            return u32::MAX;
+        }
+
+        let n = self.n as u64;
+        let mut chi2 = 0u64;
+        for (c, e) in self.counts.iter().zip(Self::EXPECTED) {
+            let e = n * e as u64 / 13;
+            let d = (*c as u64).abs_diff(e);
+            chi2 += d * d * 1000 / e;
+        }
+        chi2.min(u32::MAX as u64) as u32
+    }
+}
+
+impl Default for LsbMonitor {
+    fn default() -> Self {
+        Self::new(Self::CUTOFF)
     }
 }
@@ -1,8 +1,45 @@
 #![doc = include_str!("../README.md")]
 #![no_std]
 
+#[cfg(feature = "rp2040")]
+pub mod adc;
+#[cfg(feature = "rp2040")]
+pub mod calibrate;
+#[cfg(feature = "rp2040")]
 pub mod cap;
+pub mod characterize;
+#[cfg(feature = "rp2040")]
+pub mod dma;
+#[cfg(feature = "rp2040")]
+pub mod entropy;
+mod error;
+pub mod estimate;
+pub mod extract;
+#[cfg(feature = "rp2040")]
+pub mod fortuna;
 pub mod health;
+pub mod pool;
+pub mod rbg;
+#[cfg(feature = "rp2040")]
 pub mod rng;
+pub mod sample;
+pub mod source;
+pub mod stream;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod usbproto;
 
-pub use rng::{getrandom, getrandom_raw, setup, CapRng};
+#[cfg(feature = "rp2040")]
+pub use calibrate::{calibrate, Calibration};
+#[cfg(feature = "rp2040")]
+pub use entropy::get_entropy;
+pub use error::Error;
+#[cfg(feature = "rp2040")]
+pub use fortuna::FortunaCapRng;
+pub use health::{health_stats, HealthStats, Warmup};
+#[cfg(feature = "rp2040")]
+pub use rng::{
+    add_entropy, getrandom, getrandom_raw, register_source, reseed, setup,
+    setup_from_noise, setup_with_config, source_stats, CapRng, Config, GlobalCapRng,
+};
+pub use source::{EntropySource, NoiseSource};
@@ -0,0 +1,382 @@
+//! An entropy pool that credits entropy for noise samples.
+//!
+//! Samples are hashed into the pool, and each valid sample is credited
+//! with an entropy estimate. A seed is released only once the credited
+//! entropy reaches a threshold.
+
+#[cfg(not(feature = "defmt"))]
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+use defmt::{debug, error, info, panic, trace, warn};
+
+use sha2::{Digest, Sha256};
+
+use crate::estimate::OnlineMcv;
+use crate::health::TotalHealth;
+use crate::source::{NoiseSource, CHANNELS};
+use crate::Error;
+
+/// Entropy credited for each valid noise sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Estimate {
+    /// A fixed estimate, in millibits per sample.
+    Fixed(u32),
+    /// An online Most Common Value estimate, divided by `divisor` as a
+    /// safety factor.
+    ///
+    /// Samples are correlated so the estimator will overestimate
+    /// entropy, the divisor should be generous. Nothing is credited
+    /// until the first estimation window completes.
+    Online { divisor: u32 },
+}
+
+impl Estimate {
+    /// Window size for [`Estimate::Online`].
+    pub const WINDOW: u16 = 1024;
+
+    /// `clk_sys` frequency the default estimate applies to, in Hz.
+    pub const REFERENCE_CLK: u32 = 125_000_000;
+
+    /// The default fixed estimate, scaled for a `clk_sys` frequency in Hz.
+    ///
+    /// Timing noise is fixed in absolute time, so a slower clock resolves less
+    /// of it and is credited proportionally less. Faster clocks are credited
+    /// the same as [`REFERENCE_CLK`](Self::REFERENCE_CLK), since the extra
+    /// resolution hasn't been characterised. At least 1 millibit is credited,
+    /// so clocks below 12.5MHz are over-credited.
+    pub fn for_clock(clk_sys: u32) -> Self {
+        let clk = clk_sys.min(Self::REFERENCE_CLK) as u64;
+        let m = DEFAULT_MILLIBITS as u64 * clk / Self::REFERENCE_CLK as u64;
+        Self::Fixed((m as u32).max(1))
+    }
+
+    /// Returns whether no sample can ever be credited, so a pool would
+    /// never become ready.
+    pub(crate) fn is_zero(&self) -> bool {
+        match *self {
+            Estimate::Fixed(m) => m == 0,
+            // the MCV estimate of 6-bit samples is at most 6 bits
+            Estimate::Online { divisor } => {
+                divisor as u64 > 6000 * Self::WINDOW as u64
+            }
+        }
+    }
+}
+
+impl Default for Estimate {
+    /// 100 samples per bit at 125MHz, see [`CapRng::SEED_SAMPLES`](crate::CapRng::SEED_SAMPLES).
+    fn default() -> Self {
+        Estimate::Fixed(DEFAULT_MILLIBITS)
+    }
+}
+
+/// Per valid sample. A valid sample's rise occurred within the 6 sampled cycles
+/// of the loop, so the length of the loop's unsampled remainder doesn't
+/// affect the estimate, only the proportion of samples that are valid
+/// (5/13 at 13 cycles per loop).
+const DEFAULT_MILLIBITS: u32 = 10;
+
+/// Entropy credited to the samples for each 256 bit block of full entropy
+/// output.
+///
+/// This is twice the output length, as required for full entropy
+/// output from a vetted conditioning function by SP 800-90C.
+pub const FULL_ENTROPY_BITS: u32 = 512;
+
+/// Accumulates noise samples until sufficient entropy is credited.
+pub struct EntropyPool {
+    h: Sha256,
+    estimate: Estimate,
+    mcv: OnlineMcv,
+    // millibits
+    credited: u32,
+    // millibits * divisor not yet credited, for Estimate::Online
+    remainder: u32,
+    threshold: u32,
+}
+
+impl EntropyPool {
+    /// Creates a pool that is ready once `threshold_bits` are credited.
+    pub fn new(estimate: Estimate, threshold_bits: u32) -> Self {
+        Self {
+            h: Sha256::new(),
+            estimate,
+            mcv: OnlineMcv::new(Estimate::WINDOW),
+            credited: 0,
+            remainder: 0,
+            threshold: threshold_bits.saturating_mul(1000),
+        }
+    }
+
+    /// Hashes data into the pool without crediting any entropy.
+    pub fn mix(&mut self, data: &[u8]) {
+        self.h.update(data);
+    }
+
+    /// Credits entropy for a valid sample.
+    ///
+    /// The sample itself must also be passed to [`mix()`](Self::mix).
+    /// With [`Estimate::Online`] the fraction of a millibit left by the divisor
+    /// is carried to following samples.
+    ///
+    /// Fails with [`Error::ZeroEstimate`] when an online estimation window
+    /// completes that would credit nothing.
+    pub fn credit(&mut self, val: u8) -> Result<(), Error> {
+        let c = match self.estimate {
+            Estimate::Fixed(c) => c,
+            Estimate::Online { divisor } => {
+                let divisor = divisor.max(1);
+                if let Some(e) = self.mcv.feed(val) {
+                    if (e as u64 * Estimate::WINDOW as u64) < divisor as u64 {
+                        return Err(Error::ZeroEstimate);
+                    }
+                }
+                let m = self.mcv.estimate().unwrap_or(0) + self.remainder;
+                self.remainder = m % divisor;
+                m / divisor
+            }
+        };
+        self.credited = self.credited.saturating_add(c);
+        Ok(())
+    }
+
+    /// Credits an amount of entropy in bits.
+    pub fn credit_bits(&mut self, bits: u32) {
+        self.credited = self.credited.saturating_add(bits.saturating_mul(1000));
+    }
+
+    /// Discards all credited entropy, for example after a health test failure.
+    ///
+    /// Hashed data is kept, it can't do any harm.
+    pub fn discredit(&mut self) {
+        self.credited = 0;
+        self.remainder = 0;
+    }
+
+    /// Returns the credited entropy in bits.
+    pub fn credited_bits(&self) -> u32 {
+        self.credited / 1000
+    }
+
+    /// Returns the current online entropy estimate in millibits per sample,
+    /// if available.
+    pub fn online_estimate(&self) -> Option<u32> {
+        self.mcv.estimate()
+    }
+
+    pub fn ready(&self) -> bool {
+        self.credited >= self.threshold
+    }
+
+    /// Returns a seed if sufficient entropy has been credited.
+    ///
+    /// The pool is reset after a seed is returned.
+    pub fn take_seed(&mut self) -> Option<[u8; 32]> {
+        if !self.ready() {
+            return None;
+        }
+        self.credited = 0;
+        self.remainder = 0;
+        Some(self.h.finalize_reset().into())
+    }
+}
+
+/// Collects seeds from fresh, health tested noise samples.
+///
+/// This is used for all seeding and full entropy output. Every sample hashed
+/// into a seed has passed health tests. After a failing block of samples the
+/// seed is started again. Health tests are continuous
+/// across seeds, with each [`channel()`](NoiseSource::channel) tested separately.
+pub(crate) struct Collector {
+    estimate: Estimate,
+    health: [TotalHealth; CHANNELS],
+}
+
+impl Collector {
+    const MAX_FAILURES: usize = 3;
+
+    /// Samples health tested and hashed at a time.
+    const BLOCK: usize = 256;
+
+    /// Samples collected in each [`fill()`](NoiseSource::fill), bounding the
+    /// time a capacitor source disables interrupts.
+    const CHUNK: usize = 32;
+
+    pub fn new(estimate: Estimate) -> Self {
+        Self { estimate, health: core::array::from_fn(|_| TotalHealth::new()) }
+    }
+
+    /// Returns a hash of samples credited with at least `bits` of entropy.
+    ///
+    /// Fails after repeated health test failures, if the source times out,
+    /// or with [`Error::ZeroEstimate`].
+    pub fn collect(
+        &mut self,
+        noise: &mut (impl NoiseSource + ?Sized),
+        bits: u32,
+    ) -> Result<[u8; 32], Error> {
+        if self.estimate.is_zero() {
+            return Err(Error::ZeroEstimate);
+        }
+        let mut failures = 0;
+        let mut pool = EntropyPool::new(self.estimate, bits);
+        let mut samples = [0u8; Self::BLOCK];
+        while !pool.ready() {
+            samples.chunks_mut(Self::CHUNK).for_each(|c| noise.fill(c));
+
+            match self.test(noise, &samples) {
+                Ok(()) => (),
+                Err(Error::HealthFailed) => {
+                    failures += 1;
+                    if failures > Self::MAX_FAILURES {
+                        error!(
+                            "Health tests failed after {} retries",
+                            Self::MAX_FAILURES
+                        );
+                        return Err(Error::HealthFailed);
+                    }
+                    // earlier samples may also be degraded, start again
+                    pool = EntropyPool::new(self.estimate, bits);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            for v in samples.iter().copied() {
+                if noise.valid(v) {
+                    pool.credit(v)?;
+                }
+            }
+            pool.mix(&samples);
+        }
+
+        // OK unwrap, pool is ready
+        Ok(pool.take_seed().unwrap())
+    }
+
+    /// Health tests all valid samples.
+    fn test(
+        &mut self,
+        noise: &(impl NoiseSource + ?Sized),
+        samples: &[u8],
+    ) -> Result<(), Error> {
+        let mut r = Ok(());
+        for v in samples.iter().copied() {
+            let health = &mut self.health[noise.channel(v).min(CHANNELS - 1)];
+            if noise.timeout(v) {
+                health.timeout();
+                error!("Noise source timed out");
+                r = Err(noise.timeout_error(v));
+                break;
+            }
+            if !noise.valid(v) {
+                health.skip();
+            } else if health.test(v).is_err() {
+                r = r.and(Err(Error::HealthFailed));
+            }
+        }
+        self.health.iter_mut().for_each(|h| h.publish());
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudorandom 6-bit samples, xorshift32.
+    fn samples(n: usize) -> impl Iterator<Item = u8> {
+        let mut x = 1u32;
+        (0..n).map(move |_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8 & 0x3f
+        })
+    }
+
+    const WINDOW: usize = Estimate::WINDOW as usize;
+
+    #[test]
+    fn fixed() {
+        let mut pool = EntropyPool::new(Estimate::Fixed(1500), 3);
+        for v in samples(1) {
+            pool.credit(v).unwrap();
+        }
+        assert!(!pool.ready());
+        for v in samples(1) {
+            pool.credit(v).unwrap();
+        }
+        assert!(pool.ready());
+        assert!(pool.take_seed().is_some());
+        assert_eq!(pool.credited_bits(), 0);
+    }
+
+    #[test]
+    fn online_fraction() {
+        // the estimate is around 5 bits, a divisor larger than that
+        // credits a fraction of a millibit per sample
+        let divisor = 20_000;
+        let mut pool = EntropyPool::new(Estimate::Online { divisor }, 1000);
+        for v in samples(WINDOW) {
+            pool.credit(v).unwrap();
+        }
+        // nothing is credited until the first window completes
+        let e = pool.online_estimate().unwrap();
+        assert!(e > 4000 && e < 6000, "{e}");
+        assert_eq!(pool.credited_bits(), 0);
+
+        let n = 100 * WINDOW;
+        for v in samples(n) {
+            pool.credit(v).unwrap();
+        }
+        let e = pool.online_estimate().unwrap() as usize;
+        let expect = n * e / divisor as usize / 1000;
+        assert!(pool.credited_bits().abs_diff(expect as u32) <= 2);
+    }
+
+    #[test]
+    fn online_zero() {
+        // a window credits less than a millibit
+        let divisor = 6_000 * Estimate::WINDOW as u32;
+        let estimate = Estimate::Online { divisor };
+        assert!(!estimate.is_zero());
+        let mut pool = EntropyPool::new(estimate, 1);
+        let mut it = samples(WINDOW);
+        for v in it.by_ref().take(WINDOW - 1) {
+            pool.credit(v).unwrap();
+        }
+        assert_eq!(pool.credit(it.next().unwrap()), Err(Error::ZeroEstimate));
+
+        assert!(Estimate::Online { divisor: divisor + 1 }.is_zero());
+        assert!(Estimate::Fixed(0).is_zero());
+        assert!(!Estimate::Fixed(1).is_zero());
+    }
+
+    /// Samples that are all valid, always passing health tests.
+    struct Synthetic(core::iter::Cycle<core::ops::Range<u8>>);
+
+    impl NoiseSource for Synthetic {
+        fn fill(&mut self, buf: &mut [u8]) {
+            buf.iter_mut().for_each(|b| *b = self.0.next().unwrap());
+        }
+
+        fn valid(&self, _v: u8) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn collect_online_zero() {
+        let divisor = 6_000_000;
+        let mut c = Collector::new(Estimate::Online { divisor });
+        let mut noise = Synthetic((0..64).cycle());
+        assert_eq!(c.collect(&mut noise, 256), Err(Error::ZeroEstimate));
+
+        let mut c = Collector::new(Estimate::Online { divisor: 100 });
+        assert!(c.collect(&mut noise, 256).is_ok());
+    }
+}
@@ -0,0 +1,368 @@
+//! SP 800-90C random bit generator constructions.
+//!
+//! [`Rbg`] combines a [`NoiseSource`] with a [`Drbg`], in one of two
+//! [`Construction`]s:
+//!
+//! - [`Rbg2`](Construction::Rbg2) (RBG2(P)) outputs from the DRBG. It is
+//!   instantiated with `3s/2` bits of credited entropy for a security strength
+//!   of `s` bits, and reseeded with `s` bits on demand with [`Rbg::reseed()`]
+//!   or [`Rbg::generate_with_prediction_resistance()`].
+//!   The claimed security strength is that of the DRBG.
+//! - [`Rbg3Xor`](Construction::Rbg3Xor) (RBG3(XOR)) outputs full entropy,
+//!   each 32 byte block of DRBG output is XORed with a hash of fresh samples
+//!   credited with [`FULL_ENTROPY_BITS`]. If the noise source were to fail
+//!   undetected the output retains the security strength of the DRBG.
+//!
+//! Claims assume the entropy estimate is correct for the noise source.
+//! ChaCha20 isn't an SP 800-90A approved DRBG, so these follow the constructions
+//! without a validated claim. Any [`Drbg`] implementation can be used instead.
+//!
+//! Ref NIST SP 800-90C.
+//!
+//! # Examples
+//!
+//! Full entropy output from a capacitor, credited with its default estimate.
+//! ```ignore
+//! use caprand::cap::RawNoise;
+//! use caprand::rbg::{Construction, Rbg};
+//! use caprand::NoiseSource;
+//!
+//! let p = embassy_rp::init(Default::default());
+//! let noise = RawNoise::new(p.PIN_10, 1);
+//! let estimate = noise.estimate();
+//!
+//! let mut rbg: Rbg<_> = Rbg::new(noise, Construction::Rbg3Xor, estimate).unwrap();
+//! let mut key = [0u8; 32];
+//! rbg.generate(&mut key).unwrap();
+//! ```
+//!
+//! The global RNG can use RBG3(XOR) with
+//! `Config::construction`.
+
+use rand::Rng;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+pub use crate::pool::FULL_ENTROPY_BITS;
+use crate::pool::{Collector, Estimate};
+use crate::source::NoiseSource;
+use crate::Error;
+
+/// A deterministic random bit generator, seeded from a noise source.
+pub trait Drbg: Sized {
+    /// Security strength in bits.
+    const SECURITY_STRENGTH: u32;
+
+    /// Creates a generator from a full entropy seed.
+    fn instantiate(seed: [u8; 32]) -> Self;
+
+    /// Mixes a full entropy seed into the state.
+    fn reseed(&mut self, seed: [u8; 32]);
+
+    /// Fills `out`, then updates the state so that it can't be recovered
+    /// from later state (backtracking resistance).
+    fn generate(&mut self, out: &mut [u8]);
+}
+
+impl Drbg for ChaCha20Rng {
+    const SECURITY_STRENGTH: u32 = 256;
+
+    fn instantiate(seed: [u8; 32]) -> Self {
+        ChaCha20Rng::from_seed(seed)
+    }
+
+    fn reseed(&mut self, seed: [u8; 32]) {
+        // new key depends on both the existing state and new entropy
+        let mut k = [0u8; 32];
+        self.fill_bytes(&mut k);
+        let mut h = Sha256::new();
+        h.update(k);
+        h.update(seed);
+        *self = ChaCha20Rng::from_seed(h.finalize().into());
+    }
+
+    fn generate(&mut self, out: &mut [u8]) {
+        self.fill_bytes(out);
+        let mut k = [0u8; 32];
+        self.fill_bytes(&mut k);
+        *self = ChaCha20Rng::from_seed(k);
+    }
+}
+
+/// An SP 800-90C RBG construction, see the [module documentation](self).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Construction {
+    /// RBG2(P), DRBG output with reseeding on demand.
+    #[default]
+    Rbg2,
+    /// RBG3(XOR), full entropy samples XORed with DRBG output.
+    Rbg3Xor,
+}
+
+/// A random bit generator combining a noise source and a DRBG.
+pub struct Rbg<N, D = ChaCha20Rng> {
+    noise: N,
+    collector: Collector,
+    construction: Construction,
+    drbg: D,
+}
+
+impl<N: NoiseSource, D: Drbg> Rbg<N, D> {
+    /// Output block length for [`Construction::Rbg3Xor`], in bytes.
+    pub const BLOCK: usize = 32;
+
+    /// Instantiates the DRBG from `noise`, credited with `estimate`.
+    ///
+    /// Fails after repeated health test failures, or if the source times out.
+    pub fn new(
+        noise: N,
+        construction: Construction,
+        estimate: Estimate,
+    ) -> Result<Self, Error> {
+        let mut noise = noise;
+        let mut collector = Collector::new(estimate);
+        let seed = collector.collect(&mut noise, D::SECURITY_STRENGTH * 3 / 2)?;
+        Ok(Self { noise, collector, construction, drbg: D::instantiate(seed) })
+    }
+
+    /// Returns the construction in use.
+    pub fn construction(&self) -> Construction {
+        self.construction
+    }
+
+    /// Returns the claimed security strength in bits, that of the DRBG.
+    pub fn security_strength(&self) -> u32 {
+        D::SECURITY_STRENGTH
+    }
+
+    /// Returns whether output has full entropy, with [`Construction::Rbg3Xor`].
+    pub fn full_entropy(&self) -> bool {
+        self.construction == Construction::Rbg3Xor
+    }
+
+    /// Reseeds the DRBG with fresh samples credited with the security strength.
+    pub fn reseed(&mut self) -> Result<(), Error> {
+        let seed = self.collector.collect(&mut self.noise, D::SECURITY_STRENGTH)?;
+        self.drbg.reseed(seed);
+        Ok(())
+    }
+
+    /// Fills `out` with output of the construction.
+    ///
+    /// With [`Construction::Rbg3Xor`] fresh samples are collected for
+    /// each [`BLOCK`](Self::BLOCK).
+    pub fn generate(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        match self.construction {
+            Construction::Rbg2 => self.drbg.generate(out),
+            Construction::Rbg3Xor => {
+                for b in out.chunks_mut(Self::BLOCK) {
+                    let e = self
+                        .collector
+                        .collect(&mut self.noise, FULL_ENTROPY_BITS)?;
+                    self.drbg.generate(b);
+                    b.iter_mut().zip(e).for_each(|(b, e)| *b ^= e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reseeds, then fills `out` as [`generate()`](Self::generate).
+    pub fn generate_with_prediction_resistance(
+        &mut self,
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        self.reseed()?;
+        self.generate(out)
+    }
+
+    /// Returns the noise source.
+    pub fn into_noise(self) -> N {
+        self.noise
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples per collected block.
+    const BLOCK: usize = 256;
+
+    /// Pseudorandom 6-bit samples, counting samples filled.
+    struct Synthetic {
+        x: u32,
+        filled: usize,
+    }
+
+    impl Synthetic {
+        fn new() -> Self {
+            Self { x: 1, filled: 0 }
+        }
+    }
+
+    impl NoiseSource for Synthetic {
+        fn fill(&mut self, buf: &mut [u8]) {
+            for b in buf.iter_mut() {
+                // xorshift32
+                self.x ^= self.x << 13;
+                self.x ^= self.x >> 17;
+                self.x ^= self.x << 5;
+                *b = self.x as u8 & 0x3f;
+            }
+            self.filled += buf.len();
+        }
+
+        fn valid(&self, _v: u8) -> bool {
+            true
+        }
+    }
+
+    struct Stuck;
+
+    impl NoiseSource for Stuck {
+        fn fill(&mut self, buf: &mut [u8]) {
+            buf.fill(0x20);
+        }
+
+        fn valid(&self, _v: u8) -> bool {
+            true
+        }
+    }
+
+    struct Timeout;
+
+    impl NoiseSource for Timeout {
+        fn fill(&mut self, buf: &mut [u8]) {
+            buf.fill(0);
+        }
+
+        fn valid(&self, _v: u8) -> bool {
+            false
+        }
+
+        fn timeout(&self, v: u8) -> bool {
+            v == 0
+        }
+
+        fn timeout_error(&self, _v: u8) -> Error {
+            Error::PinStuckHigh
+        }
+    }
+
+    /// Records calls, outputs zeros.
+    #[derive(Default)]
+    struct Record {
+        seeds: usize,
+        reseeds: usize,
+        generated: [usize; 4],
+        calls: usize,
+    }
+
+    impl Drbg for Record {
+        const SECURITY_STRENGTH: u32 = 256;
+
+        fn instantiate(_seed: [u8; 32]) -> Self {
+            Self { seeds: 1, ..Default::default() }
+        }
+
+        fn reseed(&mut self, _seed: [u8; 32]) {
+            self.reseeds += 1;
+        }
+
+        fn generate(&mut self, out: &mut [u8]) {
+            out.fill(0);
+            self.generated[self.calls] = out.len();
+            self.calls += 1;
+        }
+    }
+
+    #[test]
+    fn rbg2_credit() {
+        // 1 bit per sample, 384 bits to instantiate needs 2 blocks
+        let mut rbg: Rbg<_, Record> =
+            Rbg::new(Synthetic::new(), Construction::Rbg2, Estimate::Fixed(1000))
+                .unwrap();
+        assert_eq!(rbg.noise.filled, 2 * BLOCK);
+        assert_eq!(rbg.drbg.seeds, 1);
+        assert!(!rbg.full_entropy());
+
+        // 256 bits to reseed
+        rbg.reseed().unwrap();
+        assert_eq!(rbg.noise.filled, 3 * BLOCK);
+        assert_eq!(rbg.drbg.reseeds, 1);
+
+        // output is only from the DRBG
+        let mut out = [1u8; 40];
+        rbg.generate(&mut out).unwrap();
+        assert_eq!(rbg.noise.filled, 3 * BLOCK);
+        assert_eq!(rbg.drbg.generated[..rbg.drbg.calls], [40]);
+
+        rbg.generate_with_prediction_resistance(&mut out).unwrap();
+        assert_eq!(rbg.noise.filled, 4 * BLOCK);
+        assert_eq!(rbg.drbg.reseeds, 2);
+    }
+
+    #[test]
+    fn rbg3_blocks() {
+        // 2 bits per sample, a block of samples for each output block
+        let mut rbg: Rbg<_, Record> =
+            Rbg::new(Synthetic::new(), Construction::Rbg3Xor, Estimate::Fixed(2000))
+                .unwrap();
+        assert!(rbg.full_entropy());
+        let start = rbg.noise.filled;
+
+        let mut out = [0u8; 40];
+        rbg.generate(&mut out).unwrap();
+        assert_eq!(rbg.noise.filled - start, 2 * BLOCK);
+        assert_eq!(rbg.drbg.generated[..rbg.drbg.calls], [32, 8]);
+
+        // the DRBG outputs zeros, so output is the sample hashes
+        let (a, b) = out.split_at(32);
+        assert_ne!(a, [0u8; 32]);
+        assert_ne!(b, [0u8; 8]);
+        assert_ne!(a[..8], *b);
+
+        let mut out = [0u8; 32];
+        rbg.generate(&mut out).unwrap();
+        assert_eq!(rbg.noise.filled - start, 3 * BLOCK);
+    }
+
+    #[test]
+    fn rbg_chacha() {
+        let mut rbg: Rbg<_> =
+            Rbg::new(Synthetic::new(), Construction::Rbg3Xor, Estimate::Fixed(2000))
+                .unwrap();
+        assert_eq!(rbg.security_strength(), 256);
+        let mut a = [0u8; 40];
+        let mut b = [0u8; 40];
+        rbg.generate(&mut a).unwrap();
+        rbg.generate(&mut b).unwrap();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn stuck() {
+        for c in [Construction::Rbg2, Construction::Rbg3Xor] {
+            let r: Result<Rbg<_, Record>, _> =
+                Rbg::new(Stuck, c, Estimate::Fixed(2000));
+            assert_eq!(r.err(), Some(Error::HealthFailed));
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let r: Result<Rbg<_, Record>, _> =
+            Rbg::new(Timeout, Construction::Rbg2, Estimate::Fixed(2000));
+        assert_eq!(r.err(), Some(Error::PinStuckHigh));
+    }
+
+    #[test]
+    fn zero_estimate() {
+        let r: Result<Rbg<_, Record>, _> =
+            Rbg::new(Synthetic::new(), Construction::Rbg2, Estimate::Fixed(0));
+        assert_eq!(r.err(), Some(Error::ZeroEstimate));
+    }
+}
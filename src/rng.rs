@@ -6,20 +6,38 @@ use log::{debug, error, info, trace, warn};
 #[allow(unused_imports)]
 use defmt::{debug, error, info, panic, trace, warn};
 
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
 use core::ops::DerefMut;
-use core::{cell::RefCell, convert::Infallible};
 
 use critical_section::Mutex;
 use rand_chacha::ChaCha20Rng;
 use sha2::{Digest, Sha256};
 
-use embassy_rp::{gpio::Pin, Peri};
+use embassy_rp::gpio::{AnyPin, Pin};
+use embassy_rp::Peri;
 
 use rand::Rng;
 use rand_chacha::rand_core::SeedableRng;
 
+use crate::calibrate::Calibration;
+use crate::cap::{Mode, RawNoise};
+use crate::pool::{Collector, EntropyPool, Estimate};
+use crate::rbg::Construction;
+use crate::source::{
+    EntropySource, NoiseSource, SourceStats, CAP_SOURCE, MAX_SOURCES,
+};
+use crate::Error;
+
 static RNG: Mutex<RefCell<Option<CapRng>>> = Mutex::new(RefCell::new(None));
 
+static CONSTRUCTION: Mutex<Cell<Construction>> =
+    Mutex::new(Cell::new(Construction::Rbg2));
+
+static SOURCES: Mutex<
+    RefCell<[Option<&'static mut dyn EntropySource>; MAX_SOURCES]>,
+> = Mutex::new(RefCell::new([const { None }; MAX_SOURCES]));
+
 /// A random byte generator.
 ///
 /// `buf` will be filled with random bytes.
@@ -28,16 +46,137 @@ static RNG: Mutex<RefCell<Option<CapRng>>> = Mutex::new(RefCell::new(None));
 /// This may be used by an application's getrandom custom backend,
 /// see getrandom documentation.
 pub fn getrandom(buf: &mut [u8]) -> Result<(), ()> {
+    rand::TryRng::try_fill_bytes(&mut GlobalCapRng, buf).map_err(|e| {
+        error!("getrandom failed: {:?}", e);
+    })
+}
+
+/// A handle to the global RNG used by [`getrandom()`].
+///
+/// This is zero-sized, similar to `OsRng`. [`setup()`](setup) must be called prior
+/// to use, otherwise it fails with [`Error::NotSetup`].
+/// Wrap it in [`UnwrapErr`](rand::rand_core::UnwrapErr) for generic code taking
+/// `impl CryptoRng`, which panics instead.
+///
+/// # Examples
+///
+/// ```
+/// use rand::rand_core::UnwrapErr;
+/// use rand::CryptoRng;
+///
+/// fn keygen(rng: &mut impl CryptoRng) -> [u8; 32] {
+///     let mut k = [0u8; 32];
+///     rng.fill_bytes(&mut k);
+///     k
+/// }
+///
+/// let p = embassy_rp::init(Default::default());
+/// caprand::setup(p.PIN_10).unwrap();
+///
+/// let k = keygen(&mut UnwrapErr(caprand::GlobalCapRng));
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlobalCapRng;
+
+impl rand::TryCryptoRng for GlobalCapRng {}
+
+impl rand::TryRng for GlobalCapRng {
+    type Error = Error;
+
+    fn try_next_u32(&mut self) -> Result<u32, Self::Error> {
+        let mut b = [0u8; 4];
+        self.try_fill_bytes(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn try_next_u64(&mut self) -> Result<u64, Self::Error> {
+        let mut b = [0u8; 8];
+        self.try_fill_bytes(&mut b)?;
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Self::Error> {
+        let construction = critical_section::with(|cs| {
+            let mut rng = RNG.borrow_ref_mut(cs);
+            let rng = rng.deref_mut().as_mut().ok_or(Error::NotSetup)?;
+            rng.fill_bytes(dest);
+            Ok(CONSTRUCTION.borrow(cs).get())
+        })?;
+
+        if construction == Construction::Rbg3Xor {
+            for b in dest.chunks_mut(crate::entropy::BLOCK) {
+                let mut e = [0u8; crate::entropy::BLOCK];
+                crate::entropy::get_entropy(&mut e)?;
+                b.iter_mut().zip(e).for_each(|(b, e)| *b ^= e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds entropy from an external source to the global RNG.
+///
+/// `data` is mixed in and `credited_bits` of entropy are credited to the
+/// reseed pool. `source_id` must be in `1..MAX_SOURCES`, id 0 is the capacitor,
+/// otherwise fails with [`Error::InvalidInput`].
+/// [`setup()`](setup) must be called prior to using this function, otherwise
+/// fails with [`Error::NotSetup`].
+pub fn add_entropy(
+    source_id: u8,
+    data: &[u8],
+    credited_bits: u32,
+) -> Result<(), Error> {
     critical_section::with(|cs| {
         let mut rng = RNG.borrow_ref_mut(cs);
-        let rng = rng.deref_mut();
-        if let Some(rng) = rng {
-            rng.0.fill_bytes(buf);
-            Ok(())
-        } else {
-            error!("setup() not called");
-            Err(())
+        let rng = rng.as_mut().ok_or(Error::NotSetup)?;
+        rng.add_entropy(source_id, data, credited_bits)
+    })
+}
+
+/// Registers an entropy source to be polled by [`reseed()`].
+///
+/// Fails with [`Error::InvalidInput`] if the source id is invalid or already
+/// registered.
+pub fn register_source(source: &'static mut dyn EntropySource) -> Result<(), Error> {
+    let id = source.id() as usize;
+    if id == CAP_SOURCE as usize || id >= MAX_SOURCES {
+        return Err(Error::InvalidInput);
+    }
+
+    critical_section::with(|cs| {
+        let mut sources = SOURCES.borrow_ref_mut(cs);
+        let slot = &mut sources[id];
+        if slot.is_some() {
+            return Err(Error::InvalidInput);
         }
+        *slot = Some(source);
+        Ok(())
+    })
+}
+
+/// Polls registered entropy sources, reseeding the global RNG if
+/// sufficient entropy has been credited.
+///
+/// Returns whether a reseed occurred.
+/// [`setup()`](setup) must be called prior to using this function, otherwise
+/// fails with [`Error::NotSetup`].
+pub fn reseed() -> Result<bool, Error> {
+    critical_section::with(|cs| {
+        let mut rng = RNG.borrow_ref_mut(cs);
+        let rng = rng.as_mut().ok_or(Error::NotSetup)?;
+        let mut sources = SOURCES.borrow_ref_mut(cs);
+        for s in sources.iter_mut().flatten() {
+            rng.poll_source(*s);
+        }
+        Ok(rng.reseed())
+    })
+}
+
+/// Returns statistics for an external entropy source of the global RNG.
+pub fn source_stats(source_id: u8) -> Option<SourceStats> {
+    critical_section::with(|cs| {
+        let rng = RNG.borrow_ref(cs);
+        rng.as_ref()?.source_stats(source_id)
     })
 }
 
@@ -63,12 +202,23 @@ pub unsafe fn getrandom_raw(dest: *mut u8, len: usize) -> Result<(), ()> {
 ///
 /// Call this at early startup.
 ///
+/// The default [`Config`] warms up and checks the capacitor, which takes over
+/// SYST (SysTick) to count core clock cycles. SYST's prior configuration is
+/// restored afterwards, though its current count is lost, and its interrupt
+/// is disabled meanwhile. Use [`setup_with_config()`] with
+/// `check_capacitor` false and `warmup` zero to leave SYST untouched.
+///
 /// # Arguments
 ///
 /// * pin - The GPIO pin with a capacitor attached. This will be driven low and pulled high,
 /// with timing used as a random source. The `Pin` may be used for other purposes once
 /// `setup()` completes.
 ///
+/// # Errors
+///
+/// Fails if the capacitor check fails, such as [`Error::NoCapacitor`],
+/// or after repeated health test failures.
+///
 /// # Examples
 ///
 /// ```
@@ -89,65 +239,294 @@ pub unsafe fn getrandom_raw(dest: *mut u8, len: usize) -> Result<(), ()> {
 /// ```
 /// `getrandom` custom backend requires building with `--cfg getrandom_backend="custom",
 /// see [`getrandom`] documentation.
-pub fn setup(pin: Peri<impl Pin>) -> Result<(), ()> {
+pub fn setup(pin: Peri<impl Pin>) -> Result<(), Error> {
     let r = CapRng::new(pin)?;
+    set_global(r, Construction::Rbg2);
+    Ok(())
+}
 
+/// Seed the random generator with a custom configuration.
+///
+/// With [`Construction::Rbg3Xor`] the capacitor noise source is retained
+/// for [`get_entropy()`](crate::get_entropy), and the global RNG's output is
+/// XORed with it, so the pin must be `'static`. This can only be done once,
+/// a later call fails with [`Error::InvalidInput`].
+///
+/// See [`setup()`].
+pub fn setup_with_config(
+    pin: Peri<'static, impl Pin>,
+    config: &Config,
+) -> Result<(), Error> {
+    match config.construction {
+        Construction::Rbg2 => {
+            let r = CapRng::new_with_config(pin, config)?;
+            set_global(r, Construction::Rbg2);
+        }
+        Construction::Rbg3Xor => {
+            let pin: Peri<'static, AnyPin> = pin.into();
+            let noise = cortex_m::singleton!(
+                : RawNoise<'static, AnyPin> = RawNoise::new(pin, config.low_cycles())
+            )
+            .ok_or(Error::InvalidInput)?;
+            noise.set_mode(config.mode);
+            CapRng::prepare(noise, config)?;
+            let r = CapRng::new_from_noise(noise, config)?;
+            crate::entropy::setup_from_noise(noise, config)?;
+            set_global(r, Construction::Rbg3Xor);
+        }
+    }
+    Ok(())
+}
+
+/// Seed the random generator from another noise source, such as
+/// [`AdcNoise`](crate::adc::AdcNoise).
+///
+/// Only [`Construction::Rbg2`] is supported, otherwise fails with
+/// [`Error::InvalidInput`].
+///
+/// See [`setup()`].
+pub fn setup_from_noise(
+    noise: &mut impl NoiseSource,
+    config: &Config,
+) -> Result<(), Error> {
+    if config.construction != Construction::Rbg2 {
+        return Err(Error::InvalidInput);
+    }
+    let r = CapRng::new_from_noise(noise, config)?;
+    set_global(r, Construction::Rbg2);
+    Ok(())
+}
+
+fn set_global(r: CapRng, construction: Construction) {
     critical_section::with(|cs| {
         let mut rng = RNG.borrow_ref_mut(cs);
         let _ = rng.insert(r);
+        CONSTRUCTION.borrow(cs).set(construction);
     });
-    Ok(())
+}
+
+/// Configuration for [`CapRng`].
+///
+/// The default checks and warms up the capacitor, taking over SYST during
+/// setup, see [`setup()`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Config {
+    /// Entropy credited for each valid noise sample.
+    ///
+    /// Defaults to the source's [`NoiseSource::estimate()`], for a capacitor
+    /// [`Estimate::for_clock()`] with the current `clk_sys`.
+    pub estimate: Option<Estimate>,
+    /// Credited entropy required to seed, in bits.
+    pub seed_bits: u32,
+    /// Check that a capacitor is fitted before seeding,
+    /// see [`RawNoise::check()`].
+    pub check_capacitor: bool,
+    /// Initial capacitor samples to discard before seeding,
+    /// see [`RawNoise::warm_up()`].
+    ///
+    /// Warm-up and the capacitor check take over SYST, restoring its
+    /// configuration afterwards. They are skipped if this is zero and
+    /// `check_capacitor` is false.
+    pub warmup: usize,
+    /// A `low_cycles` setting from [`calibrate()`](crate::calibrate),
+    /// otherwise 1 is used.
+    ///
+    /// Calibrate with the same `mode`, `estimate` and `seed_bits`. Only
+    /// `low_cycles` is taken from the calibration, its
+    /// [`estimate`](Calibration::estimate) ranks candidates and isn't
+    /// used for crediting.
+    pub calibration: Option<Calibration>,
+    /// Capacitor edges to time, see [`RawNoise::set_mode()`].
+    pub mode: Mode,
+    /// The construction used by the global RNG, see [`setup_with_config()`].
+    ///
+    /// Defaults to [`Construction::Rbg2`]. `CapRng` itself is always RBG2,
+    /// use [`Rbg`](crate::rbg::Rbg) for other constructions.
+    pub construction: Construction,
+}
+
+impl Config {
+    /// Returns the `low_cycles` setting for the capacitor.
+    pub fn low_cycles(&self) -> u32 {
+        self.calibration.map(|c| c.low_cycles).unwrap_or(1)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            estimate: None,
+            seed_bits: CapRng::SEED_BITS,
+            check_capacitor: true,
+            warmup: CapRng::WARMUP,
+            calibration: None,
+            mode: Mode::Rise,
+            construction: Construction::Rbg2,
+        }
+    }
 }
 
 // TODO: this is another impl of chacha20, can it use chacha20 crate instead? Is the size much?
 // TODO: have some kind of fast erasure RNG instead?
 /// A cryptographic PRNG seeded by the capacitor noise source.
-pub struct CapRng(ChaCha20Rng);
+///
+/// Entropy from other sources can be added after seeding, and the
+/// generator is reseeded once sufficient entropy is credited.
+pub struct CapRng {
+    rng: ChaCha20Rng,
+    /// Entropy from external sources, for reseeding.
+    hub: EntropyPool,
+    stats: [SourceStats; MAX_SOURCES],
+}
 
 impl CapRng {
-    /// The number of noise samples to use for seeding.
+    /// The number of valid noise samples to use for seeding with the
+    /// default fixed estimate at 125MHz.
     ///
-    /// We need to produce a 256 bit output seed.
+    /// We need to produce a 256 bit output seed. Only 5/13 of capacitor
+    /// samples are expected to be valid, around 66000 samples are taken. Slower clocks need
+    /// proportionally more samples, see [`Estimate::for_clock()`].
     pub const SEED_SAMPLES: usize = 256 * 100;
 
-    const MAX_FAILURES: usize = 3;
+    /// The default credited entropy required for a seed.
+    pub const SEED_BITS: u32 = 256;
 
-    pub fn new(pin: Peri<impl Pin>) -> Result<Self, ()> {
-        let low_cycles = 1;
-        let mut noise = crate::cap::RawNoise::new(pin, low_cycles);
+    /// The default number of warm-up samples to discard.
+    pub const WARMUP: usize = 16;
 
-        let mut valid_samples = 0;
-        let mut h = Sha256::new();
+    /// Number of samples collected and hashed at a time by
+    /// [`FortunaCapRng`](crate::FortunaCapRng).
+    pub(crate) const BLOCK: usize = 256;
+
+    pub fn new(pin: Peri<impl Pin>) -> Result<Self, Error> {
+        Self::new_with_config(pin, &Config::default())
+    }
+
+    /// Seeds once the credited entropy reaches `config.seed_bits`.
+    ///
+    /// Fails if the capacitor check fails, or after repeated health
+    /// test failures.
+    pub fn new_with_config(
+        pin: Peri<impl Pin>,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let mut noise = RawNoise::new(pin, config.low_cycles());
+        noise.set_mode(config.mode);
+        Self::prepare(&mut noise, config)?;
+        Self::new_from_noise(&mut noise, config)
+    }
+
+    /// Seeds from another noise source, such as [`AdcNoise`](crate::adc::AdcNoise).
+    pub fn new_from_noise(
+        noise: &mut impl NoiseSource,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let seed = Self::seed(noise, config)?;
+        Ok(Self {
+            rng: ChaCha20Rng::from_seed(seed),
+            // only credited by external sources
+            hub: EntropyPool::new(Estimate::Fixed(0), config.seed_bits),
+            stats: Default::default(),
+        })
+    }
+
+    /// Adds entropy from an external source.
+    ///
+    /// See [`add_entropy()`](crate::add_entropy).
+    pub fn add_entropy(
+        &mut self,
+        source_id: u8,
+        data: &[u8],
+        credited_bits: u32,
+    ) -> Result<(), Error> {
+        if source_id == CAP_SOURCE {
+            return Err(Error::InvalidInput);
+        }
+        let st =
+            self.stats.get_mut(source_id as usize).ok_or(Error::InvalidInput)?;
+
+        // prefix with the source and length so inputs are unambiguous
+        self.hub.mix(&[source_id]);
+        self.hub.mix(&(data.len() as u32).to_le_bytes());
+        self.hub.mix(data);
+        self.hub.credit_bits(credited_bits);
+
+        st.bytes = st.bytes.saturating_add(data.len() as u32);
+        st.credited_bits = st.credited_bits.saturating_add(credited_bits);
+        st.adds = st.adds.saturating_add(1);
+        Ok(())
+    }
 
-        let mut health = crate::health::TotalHealth::new();
-        let mut failures = 0;
-
-        while valid_samples < Self::SEED_SAMPLES {
-            let (v, valid) = noise
-                .next()
-                // OK unwrap, iterator doesn't end
-                .unwrap();
-            if valid {
-                valid_samples += 1;
-                if health.test(v).is_err() {
-                    valid_samples = 0;
-                    failures += 1;
-                    if failures > Self::MAX_FAILURES {
-                        error!(
-                            "Health tests failed after {} retries",
-                            Self::MAX_FAILURES
-                        );
-                        return Err(());
-                    }
+    /// Polls an external source and adds its output.
+    pub fn poll_source(&mut self, source: &mut dyn EntropySource) {
+        let id = source.id();
+        let mut buf = [0u8; 64];
+        match source.poll(&mut buf) {
+            Ok((len, bits)) if len <= buf.len() => {
+                // fails for invalid ids
+                let _ = self.add_entropy(id, &buf[..len], bits);
+            }
+            _ => {
+                warn!("Entropy source {} failed", id);
+                if let Some(st) = self.stats.get_mut(id as usize) {
+                    st.failures = st.failures.saturating_add(1);
                 }
             }
+        }
+    }
+
+    /// Reseeds if sufficient entropy has been credited from external sources.
+    ///
+    /// Returns whether a reseed occurred. This is also checked before generating output.
+    pub fn reseed(&mut self) -> bool {
+        let Some(s) = self.hub.take_seed() else {
+            return false;
+        };
+
+        // new key depends on both the existing state and new entropy
+        let mut k = [0u8; 32];
+        self.rng.fill_bytes(&mut k);
+        let mut h = Sha256::new();
+        h.update(k);
+        h.update(s);
+        self.rng = ChaCha20Rng::from_seed(h.finalize().into());
+        debug!("Reseeded from external sources");
+        true
+    }
 
-            // even "invalid" samples are included in the hash
-            h.update([v]);
+    /// Returns statistics for an external entropy source.
+    pub fn source_stats(&self, source_id: u8) -> Option<SourceStats> {
+        if source_id == CAP_SOURCE {
+            return None;
         }
+        self.stats.get(source_id as usize).copied()
+    }
 
-        let seed: [u8; 32] = h.finalize().into();
-        Ok(Self(ChaCha20Rng::from_seed(seed)))
+    /// Warms up and checks a capacitor noise source as configured.
+    pub(crate) fn prepare<P: Pin>(
+        noise: &mut RawNoise<P>,
+        config: &Config,
+    ) -> Result<(), Error> {
+        if config.warmup == 0 && !config.check_capacitor {
+            return Ok(());
+        }
+        let w = noise.warm_up(config.warmup)?;
+        if config.check_capacitor {
+            noise.check_rise(w.median)?;
+        }
+        Ok(())
+    }
+
+    /// Collects a health tested seed from a noise source.
+    ///
+    /// See [`Collector`] for failure handling.
+    pub(crate) fn seed(
+        noise: &mut impl NoiseSource,
+        config: &Config,
+    ) -> Result<[u8; 32], Error> {
+        let estimate = config.estimate.unwrap_or_else(|| noise.estimate());
+        Collector::new(estimate).collect(noise, config.seed_bits)
     }
 }
 
@@ -156,14 +535,17 @@ impl rand::TryCryptoRng for CapRng {}
 impl rand::TryRng for CapRng {
     type Error = Infallible;
     fn try_next_u32(&mut self) -> Result<u32, Self::Error> {
-        self.0.try_next_u32()
+        self.reseed();
+        self.rng.try_next_u32()
     }
 
     fn try_next_u64(&mut self) -> Result<u64, Self::Error> {
-        self.0.try_next_u64()
+        self.reseed();
+        self.rng.try_next_u64()
     }
 
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Self::Error> {
-        self.0.try_fill_bytes(dest)
+        self.reseed();
+        self.rng.try_fill_bytes(dest)
     }
 }
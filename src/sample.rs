@@ -0,0 +1,43 @@
+//! Capacitor sample format.
+//!
+//! Each [`RawNoise`](crate::cap::RawNoise) sample holds a burst of 5 pin
+//! readings from the sampling loop in bits 0-4, set from the first reading
+//! after the edge. Bit 5 is set once the edge occurred, and clear for a
+//! [`timeout()`]. Samples timing a fall also have [`FALL`] set.
+//!
+//! These don't depend on the hardware, so captured samples can be
+//! interpreted on a host.
+
+/// Returns the least significant bit set, or 8 if 0.
+///
+/// Is neither constant time nor efficient, for display purposes only.
+pub fn lsb(v: u8) -> u8 {
+    for i in 0..u8::BITS {
+        if v & 1 << i != 0 {
+            return i as u8;
+        }
+    }
+    8
+}
+
+/// Returns whether a sample is valid.
+///
+/// Samples with the first bit of a burst set are invalid, the
+/// pin may have risen during the preceding loop cycles.
+/// Timeouts are invalid.
+pub fn valid(v: u8) -> bool {
+    (v & 1) == 0 && !timeout(v)
+}
+
+/// Returns whether a sample timed out, the pin didn't rise (or fall).
+pub fn timeout(v: u8) -> bool {
+    (v & 0x20) == 0
+}
+
+/// Set in samples timing a fall, with `cap::Mode::Fall` or `cap::Mode::Both`.
+pub const FALL: u8 = 0x40;
+
+/// Returns whether a sample timed a fall rather than a rise.
+pub fn fall(v: u8) -> bool {
+    (v & FALL) != 0
+}
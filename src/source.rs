@@ -0,0 +1,93 @@
+//! Noise sources, and external entropy sources mixed into [`CapRng`](crate::CapRng).
+//!
+//! A [`NoiseSource`] produces raw samples which are health tested and
+//! credited while seeding.
+//!
+//! A noise source, usually the capacitor, is required for the initial seed.
+//! Other sources such as interrupt timing jitter or radio RSSI can be added
+//! with [`add_entropy()`](crate::add_entropy), or registered with
+//! [`register_source()`](crate::register_source) to be polled on reseed.
+//! Once entropy credited from other sources reaches the seed threshold
+//! the generator is reseeded.
+
+use crate::pool::Estimate;
+use crate::Error;
+
+/// Maximum number of sources, including the capacitor.
+pub const MAX_SOURCES: usize = 8;
+
+/// Source id of the capacitor. Other sources use ids `1..MAX_SOURCES`.
+pub const CAP_SOURCE: u8 = 0;
+
+/// Maximum number of separate measurements from a [`NoiseSource`],
+/// see [`NoiseSource::channel()`].
+pub const CHANNELS: usize = 2;
+
+/// A raw noise source used for seeding.
+///
+/// Implemented by [`RawNoise`](crate::cap::RawNoise), [`DmaNoise`](crate::dma::DmaNoise)
+/// and [`AdcNoise`](crate::adc::AdcNoise).
+/// The sample format depends on the source, capacitor samples are described in
+/// [`sample`](crate::sample).
+pub trait NoiseSource {
+    /// Fills `buf` with samples.
+    fn fill(&mut self, buf: &mut [u8]);
+
+    /// Returns whether a sample is valid for health tests and entropy crediting.
+    ///
+    /// Invalid samples are still hashed.
+    fn valid(&self, v: u8) -> bool;
+
+    /// Returns whether a sample indicates the source has failed,
+    /// such as a capacitor pin that didn't rise.
+    fn timeout(&self, _v: u8) -> bool {
+        false
+    }
+
+    /// Returns the error for a [`timeout()`](Self::timeout) sample.
+    fn timeout_error(&self, _v: u8) -> Error {
+        Error::PinStuckLow
+    }
+
+    /// Returns which measurement a sample is from, less than [`CHANNELS`].
+    ///
+    /// Sources sampling more than one measurement, such as both capacitor
+    /// edges, have health tests run separately for each.
+    fn channel(&self, _v: u8) -> usize {
+        0
+    }
+
+    /// Returns the entropy estimate used when none is configured.
+    ///
+    /// Capacitor sources scale this by the clock, see [`Estimate::for_clock()`].
+    fn estimate(&self) -> Estimate {
+        Estimate::default()
+    }
+}
+
+/// An entropy source polled by the RNG.
+pub trait EntropySource: Send {
+    /// Source id, in `1..MAX_SOURCES`.
+    fn id(&self) -> u8;
+
+    /// Fills `buf` with data from the source.
+    ///
+    /// Returns the number of bytes written and the entropy to credit in bits.
+    /// Polling occurs inside a critical section so should be short.
+    /// Errors are counted in [`SourceStats::failures`].
+    fn poll(&mut self, buf: &mut [u8]) -> Result<(usize, u32), Error>;
+}
+
+/// Statistics for an entropy source.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SourceStats {
+    /// Bytes mixed in.
+    pub bytes: u32,
+    /// Entropy credited, in bits.
+    pub credited_bits: u32,
+    /// Successful additions or polls.
+    pub adds: u32,
+    /// Failed polls.
+    pub failures: u32,
+}
@@ -0,0 +1,298 @@
+//! A binary framing for streaming raw noise samples.
+//!
+//! Each frame carries a header with capture metadata, sequence numbers
+//! and a CRC, so that long captures can be concatenated and gaps detected.
+//! The [`Encoder`] is used by firmware, [`decode()`] and [`Decoder`] can also
+//! be used on a host (build with `default-features = false`).
+//!
+//! # Format
+//!
+//! All fields are little endian.
+//!
+//! | Offset | Size | Field |
+//! |-------:|-----:|-------|
+//! | 0      | 4    | Magic `CAPR` |
+//! | 4      | 1    | Format version, [`VERSION`] |
+//! | 5      | 1    | Source kind, [`SourceKind`] |
+//! | 6      | 2    | Number of samples `n` |
+//! | 8      | 4    | Session id |
+//! | 12     | 4    | Frame sequence number |
+//! | 16     | 4    | Sample offset, count of samples in prior frames |
+//! | 20     | 4    | `clk_sys` frequency, Hz |
+//! | 24     | 4    | `low_cycles` |
+//! | 28     | 1    | Pin |
+//! | 29     | 3    | Firmware version, major minor patch |
+//! | 32     | 8    | Capacitor tag, ASCII padded with zeros |
+//! | 40     | `n`  | Samples |
+//! | 40+`n` | 4    | CRC-32 (IEEE) of all preceding frame bytes |
+
+/// Frame magic.
+pub const MAGIC: [u8; 4] = *b"CAPR";
+
+/// Format version.
+pub const VERSION: u8 = 1;
+
+/// Length of the frame header.
+pub const HEADER_LEN: usize = 40;
+
+/// Length of the frame trailer.
+pub const TRAILER_LEN: usize = 4;
+
+/// Maximum number of samples in a frame.
+pub const MAX_SAMPLES: usize = u16::MAX as usize;
+
+/// Returns the encoded length of a frame with `n` samples.
+pub const fn frame_len(n: usize) -> usize {
+    HEADER_LEN + n + TRAILER_LEN
+}
+
+/// Stream errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// More input is required.
+    Short,
+    /// Output buffer is too small, or too many samples.
+    NoSpace,
+    BadMagic,
+    /// Unsupported format version.
+    BadVersion,
+    BadCrc,
+}
+
+/// Kind of noise source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SourceKind {
+    /// Capacitor rise time samples from [`RawNoise`](crate::cap::RawNoise).
+    CapRise,
+    /// ADC samples from [`AdcNoise`](crate::adc::AdcNoise).
+    Adc,
+    Unknown(u8),
+}
+
+impl From<u8> for SourceKind {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => Self::CapRise,
+            1 => Self::Adc,
+            v => Self::Unknown(v),
+        }
+    }
+}
+
+impl From<SourceKind> for u8 {
+    fn from(v: SourceKind) -> Self {
+        match v {
+            SourceKind::CapRise => 0,
+            SourceKind::Adc => 1,
+            SourceKind::Unknown(v) => v,
+        }
+    }
+}
+
+/// Capture metadata, included in every frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Metadata {
+    pub source: SourceKind,
+    /// Identifies a capture, for example a boot count.
+    /// Sequence numbers restart for a new session.
+    pub session: u32,
+    /// `clk_sys` frequency in Hz.
+    pub clk_sys: u32,
+    pub low_cycles: u32,
+    pub pin: u8,
+    /// Firmware version, major minor patch.
+    pub firmware: [u8; 3],
+    /// Identifies the capacitor fitted, such as `b"10nF Y5V"`.
+    pub tag: [u8; 8],
+}
+
+/// A decoded frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub meta: Metadata,
+    pub seq: u32,
+    /// Count of samples in prior frames of the session.
+    pub offset: u32,
+    pub samples: &'a [u8],
+}
+
+/// Encodes samples into frames.
+pub struct Encoder {
+    meta: Metadata,
+    seq: u32,
+    offset: u32,
+}
+
+impl Encoder {
+    pub fn new(meta: Metadata) -> Self {
+        Self { meta, seq: 0, offset: 0 }
+    }
+
+    /// Encodes `samples` as a frame into `out`.
+    ///
+    /// Returns the length of the frame, [`frame_len()`].
+    pub fn encode(
+        &mut self,
+        samples: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        let n = samples.len();
+        if n > MAX_SAMPLES {
+            return Err(Error::NoSpace);
+        }
+        let out = out.get_mut(..frame_len(n)).ok_or(Error::NoSpace)?;
+
+        let m = &self.meta;
+        let (h, rest) = out.split_at_mut(HEADER_LEN);
+        h[0..4].copy_from_slice(&MAGIC);
+        h[4] = VERSION;
+        h[5] = m.source.into();
+        h[6..8].copy_from_slice(&(n as u16).to_le_bytes());
+        h[8..12].copy_from_slice(&m.session.to_le_bytes());
+        h[12..16].copy_from_slice(&self.seq.to_le_bytes());
+        h[16..20].copy_from_slice(&self.offset.to_le_bytes());
+        h[20..24].copy_from_slice(&m.clk_sys.to_le_bytes());
+        h[24..28].copy_from_slice(&m.low_cycles.to_le_bytes());
+        h[28] = m.pin;
+        h[29..32].copy_from_slice(&m.firmware);
+        h[32..40].copy_from_slice(&m.tag);
+
+        let (s, _) = rest.split_at_mut(n);
+        s.copy_from_slice(samples);
+
+        let crc = crc32(&out[..HEADER_LEN + n]);
+        out[HEADER_LEN + n..].copy_from_slice(&crc.to_le_bytes());
+
+        self.seq = self.seq.wrapping_add(1);
+        self.offset = self.offset.wrapping_add(n as u32);
+        Ok(out.len())
+    }
+}
+
+fn le32(b: &[u8]) -> u32 {
+    // OK unwrap, callers pass 4 bytes
+    u32::from_le_bytes(b.try_into().unwrap())
+}
+
+/// Decodes a frame from the start of `buf`.
+///
+/// Returns the frame and its encoded length.
+pub fn decode(buf: &[u8]) -> Result<(Frame<'_>, usize), Error> {
+    let h = buf.get(..HEADER_LEN).ok_or(Error::Short)?;
+    if h[0..4] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    if h[4] != VERSION {
+        return Err(Error::BadVersion);
+    }
+    let n = u16::from_le_bytes([h[6], h[7]]) as usize;
+    let len = frame_len(n);
+    let f = buf.get(..len).ok_or(Error::Short)?;
+
+    let crc = le32(&f[HEADER_LEN + n..]);
+    if crc != crc32(&f[..HEADER_LEN + n]) {
+        return Err(Error::BadCrc);
+    }
+
+    let meta = Metadata {
+        source: h[5].into(),
+        session: le32(&h[8..12]),
+        clk_sys: le32(&h[20..24]),
+        low_cycles: le32(&h[24..28]),
+        pin: h[28],
+        // OK unwrap, fixed lengths
+        firmware: h[29..32].try_into().unwrap(),
+        tag: h[32..40].try_into().unwrap(),
+    };
+    let frame = Frame {
+        meta,
+        seq: le32(&h[12..16]),
+        offset: le32(&h[16..20]),
+        samples: &f[HEADER_LEN..HEADER_LEN + n],
+    };
+    Ok((frame, len))
+}
+
+/// Returns the position of the next possible frame start in `buf`.
+///
+/// Used to resynchronise after a decode error.
+pub fn find_magic(buf: &[u8]) -> Option<usize> {
+    buf.windows(MAGIC.len()).position(|w| w == MAGIC)
+}
+
+/// Continuity of a frame with the previous frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Continuity {
+    /// Directly follows the previous frame.
+    Contiguous,
+    /// The first frame, or a different session.
+    NewSession,
+    /// Frames were lost.
+    Gap { frames: u32, samples: u32 },
+}
+
+/// Tracks sequence numbers across decoded frames.
+#[derive(Default)]
+pub struct Decoder {
+    // session, next seq, next offset
+    next: Option<(u32, u32, u32)>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks continuity of a decoded frame with the previous one.
+    pub fn check(&mut self, frame: &Frame) -> Continuity {
+        let n = frame.samples.len() as u32;
+        let next = (
+            frame.meta.session,
+            frame.seq.wrapping_add(1),
+            frame.offset.wrapping_add(n),
+        );
+
+        match self.next.replace(next) {
+            Some((session, seq, offset)) if session == frame.meta.session => {
+                if frame.seq == seq {
+                    Continuity::Contiguous
+                } else {
+                    Continuity::Gap {
+                        frames: frame.seq.wrapping_sub(seq),
+                        samples: frame.offset.wrapping_sub(offset),
+                    }
+                }
+            }
+            _ => Continuity::NewSession,
+        }
+    }
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut t = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        t[i] = c;
+        i += 1;
+    }
+    t
+};
+
+/// CRC-32 (IEEE 802.3), as used by zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = !0u32;
+    for b in data {
+        c = CRC_TABLE[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
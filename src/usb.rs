@@ -0,0 +1,424 @@
+//! A USB vendor class entropy device.
+//!
+//! Requires the `usb` feature. [`UsbEntropy`] adds a vendor interface to an
+//! `embassy-usb` device, outputting raw samples, conditioned output, or health
+//! counters on a bulk endpoint. The protocol is described in [`usbproto`](crate::usbproto).
+//!
+//! See the `usbnoise` example.
+
+#[cfg(not(feature = "defmt"))]
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::control::{
+    InResponse, OutResponse, Recipient, Request, RequestType,
+};
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use rand::Rng;
+
+use embassy_rp::gpio::Pin;
+use embassy_rp::Peri;
+
+use crate::cap::RawNoise;
+use crate::health::TotalHealth;
+use crate::pool::{Collector, Estimate};
+use crate::rng::{CapRng, Config};
+use crate::source::NoiseSource;
+use crate::stream::{frame_len, Encoder, Metadata};
+use crate::usbproto::*;
+use crate::Error;
+
+/// Maximum packet size of the bulk endpoint.
+pub const MAX_PACKET: u16 = 64;
+
+/// Samples per raw mode frame.
+const RAW_SAMPLES: usize = 1024;
+
+struct Shared {
+    mode: Mode,
+    low_cycles: u16,
+    samples: u32,
+    valid: u32,
+    health_failures: u32,
+    bytes_sent: u32,
+}
+
+impl Shared {
+    fn counters(&self) -> Counters {
+        Counters {
+            mode: self.mode,
+            low_cycles: self.low_cycles,
+            samples: self.samples,
+            valid: self.valid,
+            health_failures: self.health_failures,
+            bytes_sent: self.bytes_sent,
+        }
+    }
+}
+
+// Only a single device is supported.
+static SHARED: Mutex<RefCell<Shared>> = Mutex::new(RefCell::new(Shared {
+    mode: Mode::Idle,
+    low_cycles: 1,
+    samples: 0,
+    valid: 0,
+    health_failures: 0,
+    bytes_sent: 0,
+}));
+
+/// Signalled when the host changes configuration.
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+fn config() -> (Mode, u16) {
+    critical_section::with(|cs| {
+        let s = SHARED.borrow_ref(cs);
+        (s.mode, s.low_cycles)
+    })
+}
+
+fn update(f: impl FnOnce(&mut Shared)) {
+    critical_section::with(|cs| f(&mut SHARED.borrow_ref_mut(cs)))
+}
+
+/// Handles vendor control requests.
+struct Control {
+    iface: InterfaceNumber,
+}
+
+impl Control {
+    fn ours(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Vendor
+            && req.recipient == Recipient::Interface
+            && req.index == self.iface.0 as u16
+    }
+}
+
+impl Handler for Control {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if !self.ours(&req) {
+            return None;
+        }
+
+        let r = match req.request {
+            REQ_SET_MODE => match Mode::try_from(req.value) {
+                Ok(m) => {
+                    debug!("USB mode {}", m as u8);
+                    update(|s| s.mode = m);
+                    OutResponse::Accepted
+                }
+                Err(_) => OutResponse::Rejected,
+            },
+            REQ_SET_LOW_CYCLES => {
+                update(|s| s.low_cycles = req.value);
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        };
+        CHANGED.signal(());
+        Some(r)
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        req: Request,
+        buf: &'a mut [u8],
+    ) -> Option<InResponse<'a>> {
+        if !self.ours(&req) {
+            return None;
+        }
+
+        if req.request != REQ_GET_COUNTERS {
+            return Some(InResponse::Rejected);
+        }
+
+        let c = critical_section::with(|cs| SHARED.borrow_ref(cs).counters());
+        let c = c.to_bytes();
+        let l = c.len().min(buf.len());
+        buf[..l].copy_from_slice(&c[..l]);
+        Some(InResponse::Accepted(&buf[..l]))
+    }
+}
+
+/// Storage for [`UsbEntropy`], must outlive the USB device.
+pub struct State {
+    control: Control,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self { control: Control { iface: InterfaceNumber(0) } }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A USB vendor class entropy device.
+pub struct UsbEntropy<'d, D: Driver<'d>> {
+    ep: D::EndpointIn,
+    meta: Metadata,
+    config: Config,
+}
+
+impl<'d, D: Driver<'d>> UsbEntropy<'d, D> {
+    /// Adds the vendor interface to a USB device.
+    ///
+    /// `meta` is included in raw mode frames. `pin`, `low_cycles` and
+    /// `clk_sys` are filled when streaming.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut State,
+        meta: Metadata,
+    ) -> Self {
+        let mut func = builder.function(CLASS, SUBCLASS, PROTOCOL);
+        let mut iface = func.interface();
+        state.control.iface = iface.interface_number();
+        let mut alt = iface.alt_setting(CLASS, SUBCLASS, PROTOCOL, None);
+        let ep = alt.endpoint_bulk_in(None, MAX_PACKET);
+        drop(func);
+
+        builder.handler(&mut state.control);
+        Self { ep, meta, config: Config::default() }
+    }
+
+    /// Sets the capacitor configuration.
+    ///
+    /// `config.mode` applies to all modes, the remainder to conditioned and
+    /// full entropy output. `low_cycles` is always that set by the host,
+    /// `config.calibration` isn't used. Takes effect when the host next
+    /// changes mode.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Runs the device, sampling from a capacitor on `pin`.
+    ///
+    /// Output is produced according to the mode set by the host.
+    pub async fn run(&mut self, mut pin: Peri<'_, impl Pin>) -> ! {
+        loop {
+            self.ep.wait_enabled().await;
+            let (mode, low_cycles) = config();
+            let r = match mode {
+                Mode::Idle => {
+                    CHANGED.wait().await;
+                    Ok(())
+                }
+                Mode::Raw => self.raw(pin.reborrow(), low_cycles).await,
+                Mode::Conditioned => {
+                    self.conditioned(pin.reborrow(), low_cycles).await
+                }
+                Mode::Health => self.health(pin.reborrow(), low_cycles).await,
+                Mode::FullEntropy => {
+                    self.full_entropy(pin.reborrow(), low_cycles).await
+                }
+            };
+            if r.is_err() {
+                debug!("USB endpoint error");
+            }
+        }
+    }
+
+    /// Creates a capacitor noise source, warmed up and checked as configured.
+    fn prepared<'p, P: Pin>(
+        &self,
+        pin: Peri<'p, P>,
+        low_cycles: u16,
+    ) -> Result<RawNoise<'p, P>, Error> {
+        let mut noise = RawNoise::new(pin, low_cycles as u32);
+        noise.set_mode(self.config.mode);
+        CapRng::prepare(&mut noise, &self.config)?;
+        Ok(noise)
+    }
+
+    /// Collects a block of samples, with health tests.
+    ///
+    /// Returns the number of health test failures.
+    fn sample<P: Pin>(
+        noise: &mut RawNoise<P>,
+        health: &mut TotalHealth,
+        buf: &mut [u8],
+    ) -> u32 {
+        noise.fill(buf);
+        let mut valid = 0;
+        let mut failures = 0;
+        for v in buf.iter().copied() {
+            if crate::cap::timeout(v) {
+                health.timeout();
+                failures += 1;
+                continue;
+            }
+            if !crate::cap::valid(v) {
+                health.skip();
+                continue;
+            }
+            valid += 1;
+            if health.test(v).is_err() {
+                failures += 1;
+            }
+        }
+        health.publish();
+        update(|s| {
+            s.samples = s.samples.wrapping_add(buf.len() as u32);
+            s.valid = s.valid.wrapping_add(valid);
+            s.health_failures = s.health_failures.wrapping_add(failures);
+        });
+        failures
+    }
+
+    async fn raw(
+        &mut self,
+        pin: Peri<'_, impl Pin>,
+        low_cycles: u16,
+    ) -> Result<(), EndpointError> {
+        let pin_num = pin.pin();
+        let mut noise = RawNoise::new(pin, low_cycles as u32);
+        noise.set_mode(self.config.mode);
+        let mut health = TotalHealth::new();
+
+        let mut meta = self.meta.clone();
+        meta.pin = pin_num;
+        meta.low_cycles = low_cycles as u32;
+        meta.clk_sys = embassy_rp::clocks::clk_sys_freq();
+        let mut enc = Encoder::new(meta);
+
+        let mut samples = [0u8; RAW_SAMPLES];
+        let mut frame = [0u8; frame_len(RAW_SAMPLES)];
+        while config() == (Mode::Raw, low_cycles) {
+            Self::sample(&mut noise, &mut health, &mut samples);
+            // OK unwrap, frame is sized for samples
+            let l = enc.encode(&samples, &mut frame).unwrap();
+            self.write(&frame[..l]).await?;
+        }
+        Ok(())
+    }
+
+    async fn conditioned(
+        &mut self,
+        pin: Peri<'_, impl Pin>,
+        low_cycles: u16,
+    ) -> Result<(), EndpointError> {
+        let rng = self.prepared(pin, low_cycles).and_then(|mut noise| {
+            CapRng::new_from_noise(&mut Counted(&mut noise), &self.config)
+        });
+        let Ok(mut rng) = rng else {
+            update(|s| s.health_failures = s.health_failures.wrapping_add(1));
+            // wait for the host to change mode
+            CHANGED.wait().await;
+            return Ok(());
+        };
+
+        let mut b = [0u8; MAX_PACKET as usize];
+        while config() == (Mode::Conditioned, low_cycles) {
+            rng.fill_bytes(&mut b);
+            self.write(&b).await?;
+        }
+        Ok(())
+    }
+
+    async fn health(
+        &mut self,
+        pin: Peri<'_, impl Pin>,
+        low_cycles: u16,
+    ) -> Result<(), EndpointError> {
+        let mut noise = RawNoise::new(pin, low_cycles as u32);
+        noise.set_mode(self.config.mode);
+        let mut health = TotalHealth::new();
+
+        let mut samples = [0u8; RAW_SAMPLES];
+        while config() == (Mode::Health, low_cycles) {
+            Self::sample(&mut noise, &mut health, &mut samples);
+            let c = critical_section::with(|cs| SHARED.borrow_ref(cs).counters());
+            self.write(&c.to_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Outputs hashes of samples credited with [`FULL_ENTROPY_BITS`].
+    ///
+    /// After repeated health test failures output stops until the host
+    /// changes mode.
+    async fn full_entropy(
+        &mut self,
+        pin: Peri<'_, impl Pin>,
+        low_cycles: u16,
+    ) -> Result<(), EndpointError> {
+        let estimate = self.config.estimate;
+        let r = self.prepared(pin, low_cycles).map(|noise| {
+            let estimate = estimate.unwrap_or_else(|| noise.estimate());
+            (noise, Collector::new(estimate))
+        });
+        let Ok((mut noise, mut collector)) = r else {
+            update(|s| s.health_failures = s.health_failures.wrapping_add(1));
+            CHANGED.wait().await;
+            return Ok(());
+        };
+
+        while config() == (Mode::FullEntropy, low_cycles) {
+            let Ok(out) =
+                collector.collect(&mut Counted(&mut noise), FULL_ENTROPY_BITS)
+            else {
+                update(|s| s.health_failures = s.health_failures.wrapping_add(1));
+                CHANGED.wait().await;
+                return Ok(());
+            };
+            self.write(&out).await?;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        for p in data.chunks(MAX_PACKET as usize) {
+            self.ep.write(p).await?;
+            update(|s| s.bytes_sent = s.bytes_sent.wrapping_add(p.len() as u32));
+        }
+        Ok(())
+    }
+}
+
+/// Counts samples from a noise source in the device counters.
+struct Counted<'a, N>(&'a mut N);
+
+impl<N: NoiseSource> NoiseSource for Counted<'_, N> {
+    fn fill(&mut self, buf: &mut [u8]) {
+        self.0.fill(buf);
+        let valid = buf.iter().filter(|v| self.0.valid(**v)).count() as u32;
+        update(|s| {
+            s.samples = s.samples.wrapping_add(buf.len() as u32);
+            s.valid = s.valid.wrapping_add(valid);
+        });
+    }
+
+    fn valid(&self, v: u8) -> bool {
+        self.0.valid(v)
+    }
+
+    fn timeout(&self, v: u8) -> bool {
+        self.0.timeout(v)
+    }
+
+    fn timeout_error(&self, v: u8) -> Error {
+        self.0.timeout_error(v)
+    }
+
+    fn channel(&self, v: u8) -> usize {
+        self.0.channel(v)
+    }
+
+    fn estimate(&self) -> Estimate {
+        self.0.estimate()
+    }
+}
@@ -0,0 +1,114 @@
+//! Protocol for the USB vendor class entropy device.
+//!
+//! Shared by the firmware [`usb`](crate::usb) module and host software.
+//!
+//! The device has a vendor specific interface with a single bulk IN endpoint.
+//! Vendor control requests with interface recipient configure the device:
+//!
+//! | Request | Direction | `wValue` | Data |
+//! |---------|-----------|----------|------|
+//! | [`REQ_SET_MODE`] | OUT | [`Mode`] | |
+//! | [`REQ_SET_LOW_CYCLES`] | OUT | `low_cycles` | |
+//! | [`REQ_GET_COUNTERS`] | IN | | [`Counters`] |
+//!
+//! In [`Mode::Raw`] the bulk endpoint carries [`stream`](crate::stream) frames.
+//! [`Mode::Conditioned`] outputs DRBG bytes, [`Mode::FullEntropy`] outputs
+//! hashed samples, and [`Mode::Health`] outputs a [`Counters`] record after
+//! each block of health tested samples.
+
+/// Interface class, vendor specific.
+pub const CLASS: u8 = 0xff;
+/// Interface subclass identifying caprand.
+pub const SUBCLASS: u8 = 0xca;
+/// Protocol version.
+pub const PROTOCOL: u8 = 1;
+
+/// Set the output mode.
+pub const REQ_SET_MODE: u8 = 1;
+/// Set `low_cycles` for raw samples.
+pub const REQ_SET_LOW_CYCLES: u8 = 2;
+/// Read [`Counters`].
+pub const REQ_GET_COUNTERS: u8 = 3;
+
+/// Output mode of the bulk endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Mode {
+    /// No output.
+    Idle = 0,
+    /// Raw samples in stream frames.
+    Raw = 1,
+    /// Output from a seeded DRBG.
+    Conditioned = 2,
+    /// Health counters records.
+    Health = 3,
+    /// Full entropy output, each 32 byte block is a hash of samples
+    /// credited with [`FULL_ENTROPY_BITS`].
+    FullEntropy = 4,
+}
+
+pub use crate::pool::FULL_ENTROPY_BITS;
+
+impl TryFrom<u16> for Mode {
+    type Error = ();
+    fn try_from(v: u16) -> Result<Self, ()> {
+        Ok(match v {
+            0 => Self::Idle,
+            1 => Self::Raw,
+            2 => Self::Conditioned,
+            3 => Self::Health,
+            4 => Self::FullEntropy,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Device status and counters.
+///
+/// Counters are cumulative since the device started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Counters {
+    pub mode: Mode,
+    pub low_cycles: u16,
+    /// Samples collected.
+    pub samples: u32,
+    /// Valid samples, these are health tested.
+    pub valid: u32,
+    /// Health test failures.
+    pub health_failures: u32,
+    /// Bytes written to the bulk endpoint.
+    pub bytes_sent: u32,
+}
+
+impl Counters {
+    /// Encoded length.
+    pub const LEN: usize = 20;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut b = [0u8; Self::LEN];
+        b[0] = self.mode as u8;
+        // b[1] reserved
+        b[2..4].copy_from_slice(&self.low_cycles.to_le_bytes());
+        b[4..8].copy_from_slice(&self.samples.to_le_bytes());
+        b[8..12].copy_from_slice(&self.valid.to_le_bytes());
+        b[12..16].copy_from_slice(&self.health_failures.to_le_bytes());
+        b[16..20].copy_from_slice(&self.bytes_sent.to_le_bytes());
+        b
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Option<Self> {
+        let b: &[u8; Self::LEN] = b.get(..Self::LEN)?.try_into().ok()?;
+        let le32 =
+            |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        Some(Self {
+            mode: Mode::try_from(b[0] as u16).ok()?,
+            low_cycles: u16::from_le_bytes([b[2], b[3]]),
+            samples: le32(4),
+            valid: le32(8),
+            health_failures: le32(12),
+            bytes_sent: le32(16),
+        })
+    }
+}